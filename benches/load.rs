@@ -0,0 +1,209 @@
+//! Compares the streaming loader reading a file or memory against the
+//! zero-copy and the parallel loaders reading the same file memory-mapped, on
+//! a generated map.
+//!
+//! Run with `cargo bench --bench load`.
+
+use std::fs::File;
+use std::io::{BufReader, Seek, SeekFrom};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use memmap2::Mmap;
+
+use mapeditor::map::Map;
+use mapeditor::opentibia::binaryfile::{self, EventParser, NodeEvent, ParseAction};
+use mapeditor::opentibia::map::{Item, ItemAttribute, Loader};
+use mapeditor::opentibia::Position;
+
+const SIZE: u16 = 1024;
+const FLOORS: u8 = 4;
+const RUNS: u32 = 5;
+
+fn generate_map() -> Vec<u8> {
+    let mut map = Map::new();
+
+    for z in 7 - FLOORS + 1..=7 {
+        for x in 0..SIZE {
+            for y in 0..SIZE {
+                let pos = Position { x, y, z };
+                let tile = map.get_or_create(&pos).get_tile(&pos);
+
+                tile.items.push(Item {
+                    id: 100 + (x % 50),
+                    ..Default::default()
+                });
+
+                if (x + y) % 7 == 0 {
+                    tile.items.push(Item {
+                        id: 1988,
+                        attributes: vec![ItemAttribute::ActionId(x)],
+                        children: vec![Item {
+                            id: 2148,
+                            attributes: vec![ItemAttribute::Count(y as u8)],
+                            ..Default::default()
+                        }],
+                    });
+                }
+            }
+        }
+    }
+
+    map.header_mut().version = 2;
+    map.update_size().expect("failed to update the map size");
+
+    let mut data = Vec::new();
+    Loader::default()
+        .save(&mut data, &map)
+        .expect("failed to save map");
+    data
+}
+
+// The streaming loader reads the file the way it used to be read, through a
+// buffered reader
+fn load_streaming(path: &Path) -> Map {
+    let mut r = BufReader::new(File::open(path).unwrap());
+    let mut loader = Loader::open(&mut r).unwrap();
+    let mut map = Map::new();
+
+    loader
+        .load(&mut r, |pos, tile| {
+            *map.get_or_create(&pos).get_tile(&pos) = tile
+        })
+        .unwrap();
+
+    map
+}
+
+// Streaming from memory, to tell the parser apart from reading the file
+fn load_streaming_memory(data: &[u8]) -> Map {
+    let mut r = data;
+    let mut loader = Loader::open(&mut r).unwrap();
+    let mut map = Map::new();
+
+    loader
+        .load(&mut r, |pos, tile| {
+            *map.get_or_create(&pos).get_tile(&pos) = tile
+        })
+        .unwrap();
+
+    map
+}
+
+fn load_slice(data: &[u8]) -> Map {
+    let mut loader = Loader::open(data).unwrap();
+    let mut map = Map::new();
+
+    loader
+        .load_slice(data, |pos, tile| {
+            *map.get_or_create(&pos).get_tile(&pos) = tile
+        })
+        .unwrap();
+
+    map
+}
+
+fn load_parallel(data: &[u8], threads: usize) -> Map {
+    let mut loader = Loader::open(data).unwrap();
+    loader.load_parallel(data, threads).unwrap()
+}
+
+// Node parsing alone, without decoding anything
+fn parse_streaming(path: &Path) -> usize {
+    let mut r = BufReader::new(File::open(path).unwrap());
+    let mut nodes = 0;
+
+    r.seek(SeekFrom::Start(4)).unwrap();
+
+    binaryfile::streaming_parser(r, false, |_, _| {
+        nodes += 1;
+        Ok(true)
+    })
+    .unwrap();
+
+    nodes
+}
+
+fn parse_slice(data: &[u8]) -> usize {
+    let mut nodes = 0;
+    let mut parser = EventParser::new(4, Vec::new());
+
+    parser
+        .parse_slice(data, |_, _, event| {
+            if let NodeEvent::Enter { .. } = event {
+                nodes += 1;
+            }
+
+            Ok(ParseAction::Continue)
+        })
+        .unwrap();
+
+    nodes
+}
+
+// Only `f` is timed, as before the slice loader was added; counting its
+// result and dropping it (e.g. freeing a loaded map) are not
+fn bench<T, F, C>(name: &str, what: &str, mut f: F, count: C)
+where
+    F: FnMut() -> T,
+    C: Fn(&T) -> usize,
+{
+    let mut best = Duration::MAX;
+    let mut n = 0;
+
+    for _ in 0..RUNS {
+        let start = Instant::now();
+        let result = f();
+        best = best.min(start.elapsed());
+        n = count(&result);
+    }
+
+    println!(
+        "{:<16} {:>8.2}ms ({} {})",
+        name,
+        best.as_secs_f64() * 1000.,
+        n,
+        what
+    );
+}
+
+fn bench_load<F>(name: &str, f: F)
+where
+    F: FnMut() -> Map,
+{
+    bench(name, "tiles", f, |map| map.tiles().count());
+}
+
+fn main() {
+    let path = std::env::temp_dir().join("mapeditor-bench.otbm");
+    std::fs::write(&path, generate_map()).expect("failed to write map");
+
+    let file = File::open(&path).unwrap();
+    let data = unsafe { Mmap::map(&file) }.unwrap();
+    println!("generated {:.1} MB map", data.len() as f64 / 1e6);
+
+    bench(
+        "parse streaming",
+        "nodes",
+        || parse_streaming(&path),
+        |&n| n,
+    );
+    bench("parse slice", "nodes", || parse_slice(&data), |&n| n);
+
+    bench_load("streaming", || load_streaming(&path));
+    bench_load("streaming memory", || load_streaming_memory(&data));
+    bench_load("slice", || load_slice(&data));
+
+    let threads = std::thread::available_parallelism().map_or(1, |n| n.get());
+
+    let mut thread_counts = vec![1, 2, 4, threads];
+    thread_counts.retain(|&n| n <= threads);
+    thread_counts.dedup();
+
+    for n in thread_counts {
+        bench_load(&format!("parallel x{}", n), || load_parallel(&data, n));
+    }
+
+    drop(data);
+    std::fs::remove_file(&path).unwrap();
+}
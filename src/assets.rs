@@ -0,0 +1,118 @@
+use std::fmt;
+
+use crate::clientversion::Client;
+use crate::datcontainer::DatContainer;
+use crate::opentibia::itemtypes;
+
+/// A mismatch between the item types, the dat and the spr file.
+#[derive(Debug, PartialEq)]
+pub enum AssetIssue {
+    /// An item type refers to a client id the dat doesn't have
+    MissingClientId { server_id: u16, client_id: u16 },
+    /// A dat item refers to sprites beyond the end of the spr file
+    SpriteOutOfRange { client_id: u16, sprite_id: u32 },
+    /// The dat is not from the client the OTB was made for
+    SignatureMismatch {
+        client: &'static str,
+        expected: u32,
+        found: u32,
+    },
+    /// A dat item that no item type refers to
+    UnreferencedClientId { client_id: u16 },
+}
+
+impl AssetIssue {
+    /// Whether the files can't be used together. Unreferenced client ids
+    /// are common in item types made for newer clients.
+    pub fn is_error(&self) -> bool {
+        !matches!(self, AssetIssue::UnreferencedClientId { .. })
+    }
+}
+
+impl fmt::Display for AssetIssue {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match self {
+            AssetIssue::MissingClientId {
+                server_id,
+                client_id,
+            } => write!(
+                fmt,
+                "item {} uses client id {}, which is not in the dat",
+                server_id, client_id
+            ),
+            AssetIssue::SpriteOutOfRange {
+                client_id,
+                sprite_id,
+            } => write!(
+                fmt,
+                "client id {} uses sprite {}, which is not in the spr",
+                client_id, sprite_id
+            ),
+            AssetIssue::SignatureMismatch {
+                client,
+                expected,
+                found,
+            } => write!(
+                fmt,
+                "the OTB is for client {} with dat signature {:08X}, but the dat has {:08X}",
+                client, expected, found
+            ),
+            AssetIssue::UnreferencedClientId { client_id } => {
+                write!(fmt, "client id {} is not used by any item", client_id)
+            }
+        }
+    }
+}
+
+/// Checks that the item types, the dat and a spr with `num_sprites` sprites
+/// belong together.
+pub fn check(otb: &itemtypes::Container, dat: &DatContainer, num_sprites: u32) -> Vec<AssetIssue> {
+    let mut issues = Vec::new();
+
+    if let Some(client) = Client::from_otb_version(otb.version.1) {
+        if dat.signature != client.dat_signature {
+            issues.push(AssetIssue::SignatureMismatch {
+                client: client.name,
+                expected: client.dat_signature,
+                found: dat.signature,
+            });
+        }
+    }
+
+    // Dat items start at client id 100
+    let mut referenced = vec![false; dat.items.len()];
+
+    for (server_id, item) in &otb.items {
+        let client_id = match item.client_id {
+            Some(client_id) => client_id,
+            None => continue,
+        };
+
+        match (client_id as usize).checked_sub(100) {
+            Some(index) if index < referenced.len() => referenced[index] = true,
+            _ => issues.push(AssetIssue::MissingClientId {
+                server_id: server_id as u16,
+                client_id,
+            }),
+        }
+    }
+
+    for (index, thing) in dat.items.iter().enumerate() {
+        let client_id = (index + 100) as u16;
+
+        if let Some(&sprite_id) = thing.sprite_ids.iter().max() {
+            if sprite_id > num_sprites {
+                issues.push(AssetIssue::SpriteOutOfRange {
+                    client_id,
+                    sprite_id,
+                });
+            }
+        }
+
+        if !referenced[index] {
+            issues.push(AssetIssue::UnreferencedClientId { client_id });
+        }
+    }
+
+    issues
+}
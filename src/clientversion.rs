@@ -0,0 +1,216 @@
+use serde::Deserialize;
+
+/// How a client numbers the flags of its dat things. Flags are read into
+/// `datcontainer::Attribute`, which follows the 10.x numbering.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+pub enum FlagLayout {
+    /// 7.40 to 7.50: no ground border flag, and most flags are ordered
+    /// differently
+    V740,
+    /// 7.55 to 7.72: like 8.60, but with a floor change flag
+    V755,
+    /// 7.80 to 8.54: like 8.60, but with a charges flag at 8
+    V780,
+    /// 8.60 to 9.86
+    V860,
+    /// 10.10 and newer: 8.60 with a no move animation flag at 16
+    V1010,
+}
+
+/// The parsing rules for the dat and spr files of a client. Clients missing
+/// from `CLIENTS` can be described with one directly.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+pub struct Profile {
+    pub flags: FlagLayout,
+    /// Sprite ids and the sprite count are u32 instead of u16 (9.60+)
+    pub extended: bool,
+    /// Things have a pattern depth byte (7.55+)
+    pub pattern_depth: bool,
+    /// Animations have a mode, loop count and frame durations (10.50+)
+    pub enhanced_animations: bool,
+    /// Outfits are split into idle and walking frame groups (10.57+)
+    pub frame_groups: bool,
+}
+
+impl Default for Profile {
+    /// The newest supported client.
+    fn default() -> Profile {
+        Profile {
+            flags: FlagLayout::V1010,
+            extended: true,
+            pattern_depth: true,
+            enhanced_animations: true,
+            frame_groups: true,
+        }
+    }
+}
+
+impl Profile {
+    const V740: Profile = Profile {
+        flags: FlagLayout::V740,
+        extended: false,
+        pattern_depth: false,
+        enhanced_animations: false,
+        frame_groups: false,
+    };
+
+    const V755: Profile = Profile {
+        flags: FlagLayout::V755,
+        pattern_depth: true,
+        ..Profile::V740
+    };
+
+    const V780: Profile = Profile {
+        flags: FlagLayout::V780,
+        ..Profile::V755
+    };
+
+    const V860: Profile = Profile {
+        flags: FlagLayout::V860,
+        ..Profile::V755
+    };
+
+    const V1098: Profile = Profile {
+        flags: FlagLayout::V1010,
+        extended: true,
+        pattern_depth: true,
+        enhanced_animations: true,
+        frame_groups: true,
+    };
+
+    /// Maps a flag byte to its 10.x number.
+    pub fn flag(&self, raw: u8) -> u8 {
+        // Last flag before the end marker, in the 8.60 numbering
+        const LAST_V860: u8 = 34;
+
+        let raw = match self.flags {
+            FlagLayout::V1010 => return raw,
+            FlagLayout::V860 => raw,
+            FlagLayout::V780 => match raw {
+                8 => return Profile::CHARGES,
+                9..=35 => raw - 1,
+                _ => raw,
+            },
+            FlagLayout::V755 => match raw {
+                23 => return Profile::FLOOR_CHANGE,
+                _ => raw,
+            },
+            FlagLayout::V740 => match raw {
+                // Force use and multi use are swapped
+                6 => 6,
+                5 => 7,
+                1..=4 | 7..=15 => raw + 1,
+                16 => 21,
+                17 => return Profile::FLOOR_CHANGE,
+                18 => 30,
+                19 => 25,
+                20 => 24,
+                22 => 28,
+                23 => 20,
+                24 => 26,
+                25 => 17,
+                26 => 18,
+                27 => 19,
+                28 => 27,
+                _ => raw,
+            },
+        };
+
+        // 10.10 inserted the no move animation flag at 16
+        match raw {
+            16..=LAST_V860 => raw + 1,
+            _ => raw,
+        }
+    }
+
+    /// 10.x numbers of flags that only older clients have.
+    pub(crate) const CHARGES: u8 = 0xFC;
+    pub(crate) const FLOOR_CHANGE: u8 = 0xFD;
+}
+
+/// A client whose files are known by their signatures.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Client {
+    pub name: &'static str,
+    /// The client version stored in items.otb, i.e. its minor version
+    pub otb_version: Option<u32>,
+    pub dat_signature: u32,
+    pub spr_signature: u32,
+    pub profile: Profile,
+}
+
+pub const CLIENTS: &[Client] = &[
+    Client {
+        name: "7.40",
+        otb_version: None,
+        dat_signature: 0x41BF_619C,
+        spr_signature: 0x41B9_EA86,
+        profile: Profile::V740,
+    },
+    Client {
+        name: "7.60",
+        otb_version: Some(3),
+        dat_signature: 0x439D_5A33,
+        spr_signature: 0x4398_52BE,
+        profile: Profile::V755,
+    },
+    Client {
+        name: "8.00",
+        otb_version: Some(7),
+        dat_signature: 0x467F_D7E6,
+        spr_signature: 0x467F_9E74,
+        profile: Profile::V780,
+    },
+    Client {
+        name: "8.10",
+        otb_version: Some(8),
+        dat_signature: 0x475D_3747,
+        spr_signature: 0x475D_0B01,
+        profile: Profile::V780,
+    },
+    Client {
+        name: "8.54",
+        otb_version: Some(17),
+        dat_signature: 0x4B28_B89E,
+        spr_signature: 0x4B1E_2CAA,
+        profile: Profile::V780,
+    },
+    Client {
+        name: "8.60",
+        otb_version: Some(20),
+        dat_signature: 0x4C2C_7993,
+        spr_signature: 0x4C22_0594,
+        profile: Profile::V860,
+    },
+    Client {
+        name: "10.98",
+        otb_version: Some(57),
+        dat_signature: 0x0000_42A3,
+        spr_signature: 0x57BB_D603,
+        profile: Profile::V1098,
+    },
+];
+
+impl Client {
+    pub fn named(name: &str) -> Option<&'static Client> {
+        CLIENTS.iter().find(|client| client.name == name)
+    }
+
+    pub fn from_dat_signature(signature: u32) -> Option<&'static Client> {
+        CLIENTS
+            .iter()
+            .find(|client| client.dat_signature == signature)
+    }
+
+    pub fn from_spr_signature(signature: u32) -> Option<&'static Client> {
+        CLIENTS
+            .iter()
+            .find(|client| client.spr_signature == signature)
+    }
+
+    pub fn from_otb_version(version: u32) -> Option<&'static Client> {
+        CLIENTS
+            .iter()
+            .find(|client| client.otb_version == Some(version))
+    }
+}
@@ -0,0 +1,320 @@
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufRead, Read, Write};
+use std::mem;
+use std::ops::Deref;
+use std::path::Path;
+
+use flate2::bufread::GzDecoder;
+use flate2::write::GzEncoder;
+use memmap2::Mmap;
+use xz2::bufread::XzDecoder;
+use xz2::write::XzEncoder;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Compression {
+    None,
+    Gzip,
+    Xz,
+}
+
+impl Compression {
+    const GZIP_MAGIC: &'static [u8] = &[0x1F, 0x8B];
+    const XZ_MAGIC: &'static [u8] = &[0xFD, b'7', b'z', b'X', b'Z', 0x00];
+
+    /// Detects the compression of a file from its first bytes.
+    pub fn detect(header: &[u8]) -> Compression {
+        if header.starts_with(Compression::GZIP_MAGIC) {
+            Compression::Gzip
+        } else if header.starts_with(Compression::XZ_MAGIC) {
+            Compression::Xz
+        } else {
+            Compression::None
+        }
+    }
+
+    /// Picks the compression for a file from its extension, e.g. `.otbm.gz`.
+    pub fn from_path(path: &Path) -> Compression {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("gz") => Compression::Gzip,
+            Some("xz") => Compression::Xz,
+            _ => Compression::None,
+        }
+    }
+}
+
+/// Wraps `r` in a decompressor if it starts with gzip or xz magic bytes.
+/// Nothing is consumed while looking at them.
+pub fn decoder<'a, R>(mut r: R) -> io::Result<Box<dyn Read + 'a>>
+where
+    R: BufRead + 'a,
+{
+    let compression = Compression::detect(r.fill_buf()?);
+
+    Ok(match compression {
+        Compression::None => Box::new(r),
+        Compression::Gzip => Box::new(GzDecoder::new(r)),
+        Compression::Xz => Box::new(XzDecoder::new(r)),
+    })
+}
+
+/// Decompresses a map read in several steps through different readers over
+/// the same stream, e.g. by `Loader::open` and then by `Loader::load`. The
+/// compression is detected from the first bytes, and what has been read and
+/// decompressed but not consumed yet is kept in between.
+#[derive(Default)]
+pub struct StreamDecoder {
+    state: DecoderState,
+    // Decompressed (or plain) bytes not returned by `read` yet
+    pending: Vec<u8>,
+    pending_start: usize,
+}
+
+#[derive(Default)]
+enum DecoderState {
+    #[default]
+    Detect,
+    None,
+    Gzip(flate2::write::GzDecoder<Vec<u8>>),
+    Xz(xz2::write::XzDecoder<Vec<u8>>),
+    Finished,
+}
+
+impl fmt::Debug for StreamDecoder {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        let state = match self.state {
+            DecoderState::Detect => "Detect",
+            DecoderState::None => "None",
+            DecoderState::Gzip(_) => "Gzip",
+            DecoderState::Xz(_) => "Xz",
+            DecoderState::Finished => "Finished",
+        };
+
+        fmt.debug_struct("StreamDecoder")
+            .field("state", &state)
+            .field("pending", &(self.pending.len() - self.pending_start))
+            .finish()
+    }
+}
+
+impl StreamDecoder {
+    const CHUNK_SIZE: usize = 32 * 1024;
+
+    /// Reads the next part of the stream from `r`, which has to continue
+    /// where the reader given last time stopped.
+    pub fn reader<'a, R>(&'a mut self, r: R) -> StreamReader<'a, R>
+    where
+        R: Read,
+    {
+        StreamReader {
+            decoder: self,
+            inner: r,
+        }
+    }
+
+    /// Takes the bytes read ahead but not returned yet if the stream turned
+    /// out not to be compressed. The rest of it can then be read from the
+    /// underlying reader directly instead of through `reader`. Returns `None`
+    /// for compressed streams or before anything was read.
+    pub fn take_uncompressed(&mut self) -> Option<Vec<u8>> {
+        match self.state {
+            DecoderState::None => {
+                let mut pending = mem::take(&mut self.pending);
+                pending.drain(..self.pending_start);
+                self.pending_start = 0;
+
+                Some(pending)
+            }
+            _ => None,
+        }
+    }
+
+    fn detect<R: Read>(&mut self, r: &mut R) -> io::Result<()> {
+        let mut head = Vec::with_capacity(Compression::XZ_MAGIC.len());
+        r.take(Compression::XZ_MAGIC.len() as u64)
+            .read_to_end(&mut head)?;
+
+        self.state = match Compression::detect(&head) {
+            Compression::None => DecoderState::None,
+            Compression::Gzip => DecoderState::Gzip(flate2::write::GzDecoder::new(Vec::new())),
+            Compression::Xz => DecoderState::Xz(xz2::write::XzDecoder::new(Vec::new())),
+        };
+
+        match self.state {
+            DecoderState::None => self.pending = head,
+            _ => self.decompress(&head)?,
+        }
+
+        Ok(())
+    }
+
+    fn decompress(&mut self, data: &[u8]) -> io::Result<()> {
+        let output = match &mut self.state {
+            DecoderState::Gzip(decoder) => {
+                decoder.write_all(data)?;
+                decoder.flush()?;
+                decoder.get_mut()
+            }
+            DecoderState::Xz(decoder) => {
+                decoder.write_all(data)?;
+                decoder.flush()?;
+                decoder.get_mut()
+            }
+            _ => unreachable!("not decompressing"),
+        };
+
+        self.pending.clear();
+        self.pending.append(output);
+        self.pending_start = 0;
+
+        Ok(())
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        let output = match mem::replace(&mut self.state, DecoderState::Finished) {
+            DecoderState::Gzip(decoder) => decoder.finish()?,
+            DecoderState::Xz(mut decoder) => decoder.finish()?,
+            _ => Vec::new(),
+        };
+
+        self.pending = output;
+        self.pending_start = 0;
+
+        Ok(())
+    }
+}
+
+/// Reader returned by `StreamDecoder::reader`.
+pub struct StreamReader<'a, R> {
+    decoder: &'a mut StreamDecoder,
+    inner: R,
+}
+
+impl<R> Read for StreamReader<'_, R>
+where
+    R: Read,
+{
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let decoder = &mut *self.decoder;
+
+        loop {
+            let pending = &decoder.pending[decoder.pending_start..];
+
+            if !pending.is_empty() {
+                let len = pending.len().min(buf.len());
+                buf[..len].copy_from_slice(&pending[..len]);
+                decoder.pending_start += len;
+                return Ok(len);
+            }
+
+            match decoder.state {
+                DecoderState::Detect => decoder.detect(&mut self.inner)?,
+                DecoderState::None => return self.inner.read(buf),
+                DecoderState::Finished => return Ok(0),
+                DecoderState::Gzip(_) | DecoderState::Xz(_) => {
+                    let mut chunk = [0; StreamDecoder::CHUNK_SIZE];
+
+                    match self.inner.read(&mut chunk)? {
+                        0 => decoder.finish()?,
+                        len => decoder.decompress(&chunk[..len])?,
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Writer compressing everything written to it. `finish` has to be called
+/// to write the end of the compressed stream.
+pub enum Encoder<W>
+where
+    W: Write,
+{
+    None(W),
+    Gzip(GzEncoder<W>),
+    Xz(XzEncoder<W>),
+}
+
+impl<W> Encoder<W>
+where
+    W: Write,
+{
+    pub fn new(w: W, compression: Compression) -> Encoder<W> {
+        match compression {
+            Compression::None => Encoder::None(w),
+            Compression::Gzip => Encoder::Gzip(GzEncoder::new(w, flate2::Compression::default())),
+            Compression::Xz => Encoder::Xz(XzEncoder::new(w, 6)),
+        }
+    }
+
+    pub fn finish(self) -> io::Result<W> {
+        match self {
+            Encoder::None(w) => Ok(w),
+            Encoder::Gzip(w) => w.finish(),
+            Encoder::Xz(w) => w.finish(),
+        }
+    }
+}
+
+impl<W> Write for Encoder<W>
+where
+    W: Write,
+{
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Encoder::None(w) => w.write(buf),
+            Encoder::Gzip(w) => w.write(buf),
+            Encoder::Xz(w) => w.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Encoder::None(w) => w.flush(),
+            Encoder::Gzip(w) => w.flush(),
+            Encoder::Xz(w) => w.flush(),
+        }
+    }
+}
+
+/// Contents of a file read with `read_file`.
+#[derive(Debug)]
+pub enum FileData {
+    Mapped(Mmap),
+    Decompressed(Vec<u8>),
+}
+
+impl Deref for FileData {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            FileData::Mapped(data) => data,
+            FileData::Decompressed(data) => data,
+        }
+    }
+}
+
+/// Memory-maps a file, or decompresses it into memory if it is compressed.
+pub fn read_file<P>(path: P) -> io::Result<FileData>
+where
+    P: AsRef<Path>,
+{
+    let file = File::open(path)?;
+
+    // Safety: the mapping may be kept for the whole editing session by
+    // `AreaPager`, until all areas of the map are loaded. The editor never
+    // writes to a file in place (`Loader::save_file` writes a new file and
+    // renames it over the old one, which keeps the mapped data intact), and
+    // the files are not expected to be truncated by anyone else meanwhile
+    let data = unsafe { Mmap::map(&file) }?;
+
+    if Compression::detect(&data) == Compression::None {
+        return Ok(FileData::Mapped(data));
+    }
+
+    let mut decompressed = Vec::new();
+    decoder(&data[..])?.read_to_end(&mut decompressed)?;
+
+    Ok(FileData::Decompressed(decompressed))
+}
@@ -1,3 +1,4 @@
+use crate::clientversion::{Client, FlagLayout, Profile};
 use crate::helpers::ReadExt;
 use std::io;
 
@@ -6,12 +7,20 @@ use num_derive::FromPrimitive;
 
 pub struct DatContainer {
     pub signature: u32,
+    /// The rules the file was parsed with
+    pub profile: Profile,
 
     // index 0 = item id 100
     pub items: Vec<Thing>,
+    // index 0 = look type / effect id 1
+    pub creatures: Vec<Thing>,
+    pub effects: Vec<Thing>,
+    pub missiles: Vec<Thing>,
 }
 
-#[derive(Debug, FromPrimitive, PartialEq)]
+/// Flags of a thing, numbered like 10.x clients do. Older numberings are
+/// mapped to these by `Profile::flag`.
+#[derive(Clone, Copy, Debug, FromPrimitive, PartialEq)]
 pub enum Attribute {
     Ground = 0,
     GroundBorder,
@@ -50,14 +59,69 @@ pub enum Attribute {
     Market,
     DefaultAction,
 
+    // Only in older clients
+    Charges = 0xFC,
+    FloorChange = 0xFD,
+
     Usable = 0xFE,
     End = 0xFF,
 }
 
+/// A flag of a thing along with its data, as read from the file.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Flag {
+    /// The flag byte in the numbering of the client
+    pub raw: u8,
+    pub attribute: Attribute,
+    pub data: FlagData,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum FlagData {
+    None,
+    /// Ground speed, maximum text length, elevation, minimap color, cloth
+    /// slot, lens help or default action
+    Value(u16),
+    Light {
+        intensity: u16,
+        color: u16,
+    },
+    Displacement {
+        x: u16,
+        y: u16,
+    },
+    Market {
+        category: u16,
+        trade_as: u16,
+        show_as: u16,
+        name: String,
+        vocation: u16,
+        level: u16,
+    },
+}
+
+/// How the frames of an animated thing are played (10.50+).
+#[derive(Clone, Debug, PartialEq)]
+pub struct Animation {
+    /// 0 if the animation runs on its own, 1 if it is synchronized
+    pub mode: u8,
+    pub loop_count: i32,
+    pub start_phase: u8,
+    /// Minimum and maximum duration of each frame in milliseconds
+    pub durations: Vec<(u32, u32)>,
+}
+
 #[derive(Debug)]
 pub struct Thing {
+    /// Flags in the order they are stored, including those only kept for
+    /// writing the thing back
+    pub flags: Vec<Flag>,
+
     pub width: u8,
     pub height: u8,
+    /// Size the sprites are scaled to, only stored for things larger than
+    /// one sprite
+    pub exact_size: Option<u8>,
     pub layers: u8,
 
     pub pattern_width: u8,
@@ -67,107 +131,202 @@ pub struct Thing {
     pub displacement: (u16, u16),
     pub elevation: u16,
 
+    pub animation_length: u8,
+    pub animation: Option<Animation>,
     pub sprite_ids: Vec<u32>,
+
+    /// Type of the frame group an outfit's frames were read from, for
+    /// clients with frame groups (10.57+)
+    pub group_type: Option<u8>,
+    /// The frame groups of an outfit after the first one
+    pub groups: Vec<Thing>,
 }
 
 impl Thing {
-    pub fn deserialize(r: &mut dyn io::Read) -> io::Result<Thing> {
-        let mut displacement = (0, 0);
-        let mut elevation = 0;
+    /// Frame group type of an outfit's walking frames.
+    pub const WALKING: u8 = 1;
+
+    pub fn deserialize(r: &mut dyn io::Read, profile: &Profile) -> io::Result<Thing> {
+        let flags = Thing::read_flags(r, profile)?;
+
+        let mut thing = Thing::read_frames(r, profile, &flags)?;
+        thing.flags = flags;
+
+        Ok(thing)
+    }
+
+    /// Reads a creature outfit, whose frames may be split into groups.
+    pub fn deserialize_outfit(r: &mut dyn io::Read, profile: &Profile) -> io::Result<Thing> {
+        if !profile.frame_groups {
+            return Thing::deserialize(r, profile);
+        }
+
+        let flags = Thing::read_flags(r, profile)?;
+
+        let group_count = r.read_byte()?;
+        let mut groups = Vec::with_capacity(group_count as usize);
+
+        for _ in 0..group_count {
+            let group_type = r.read_byte()?;
+
+            let mut group = Thing::read_frames(r, profile, &flags)?;
+            group.group_type = Some(group_type);
+            groups.push(group);
+        }
+
+        let mut groups = groups.into_iter();
+        let mut thing = groups.next().ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidData, "outfit without frame groups")
+        })?;
+        thing.flags = flags;
+        thing.groups = groups.collect();
+
+        Ok(thing)
+    }
+
+    /// Frames of a walking outfit, which have their own frame group since
+    /// 10.57. Older outfits walk with the idle frames.
+    pub fn walking(&self) -> Option<&Thing> {
+        self.groups
+            .iter()
+            .find(|group| group.group_type == Some(Thing::WALKING))
+    }
+
+    fn read_flags(r: &mut dyn io::Read, profile: &Profile) -> io::Result<Vec<Flag>> {
+        let mut flags = Vec::new();
 
         loop {
-            // TODO: return custom error
             let raw_attr = r.read_byte()?;
-            let attr =
-                Attribute::from_u8(raw_attr).expect(&format!("unknown attribute {}", raw_attr));
+            let attr = match Attribute::from_u8(profile.flag(raw_attr)) {
+                Some(attr) => attr,
+                None => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("unknown dat flag {} for profile {:?}", raw_attr, profile),
+                    ))
+                }
+            };
 
-            {
+            let data = {
                 use self::Attribute::*;
 
                 match attr {
                     End => break,
 
-                    Ground | Writable | WritableOnce => {
-                        let _speed = r.read_u16()?;
-                    }
-
-                    Light => {
-                        let _intensity = r.read_u16()?;
-                        let _color = r.read_u16()?;
-                    }
-
-                    Displacement => {
-                        let x = r.read_u16()?;
-                        let y = r.read_u16()?;
-
-                        displacement = (x, y);
-                    }
-
-                    Elevation => {
-                        elevation = r.read_u16()?;
-                    }
+                    Ground | Writable | WritableOnce | Elevation | DefaultAction | MinimapColor
+                    | Cloth | LensHelp => FlagData::Value(r.read_u16()?),
+
+                    Light => FlagData::Light {
+                        intensity: r.read_u16()?,
+                        color: r.read_u16()?,
+                    },
+
+                    // Fixed before 7.55
+                    Displacement if profile.flags == FlagLayout::V740 => FlagData::None,
+                    Displacement => FlagData::Displacement {
+                        x: r.read_u16()?,
+                        y: r.read_u16()?,
+                    },
+
+                    Market => FlagData::Market {
+                        category: r.read_u16()?,
+                        trade_as: r.read_u16()?,
+                        show_as: r.read_u16()?,
+                        name: r.read_string()?,
+                        vocation: r.read_u16()?,
+                        level: r.read_u16()?,
+                    },
+
+                    _ => FlagData::None,
+                }
+            };
 
-                    DefaultAction | MinimapColor | Cloth | LensHelp => {
-                        r.read_u16()?;
-                    }
+            flags.push(Flag {
+                raw: raw_attr,
+                attribute: attr,
+                data,
+            });
+        }
 
-                    Market => {
-                        let _category = r.read_u16()?;
-                        let _trade_id = r.read_u16()?;
-                        let _show_id = r.read_u16()?;
-                        let _name = r.read_string()?;
+        Ok(flags)
+    }
 
-                        let _voc = r.read_u16()?;
-                        let _level = r.read_u16()?;
-                    }
+    fn read_frames(r: &mut dyn io::Read, profile: &Profile, flags: &[Flag]) -> io::Result<Thing> {
+        let mut displacement = (0, 0);
+        let mut elevation = 0;
 
-                    _ => {}
+        for flag in flags {
+            match (flag.attribute, &flag.data) {
+                (Attribute::Displacement, FlagData::Displacement { x, y }) => {
+                    displacement = (*x, *y)
                 }
+                (Attribute::Displacement, _) => displacement = (8, 8),
+                (Attribute::Elevation, FlagData::Value(value)) => elevation = *value,
+                _ => {}
             }
         }
 
         let width = r.read_byte()?;
         let height = r.read_byte()?;
 
-        if width > 1 || height > 1 {
-            r.read_byte()?;
-        }
+        let exact_size = match width > 1 || height > 1 {
+            true => Some(r.read_byte()?),
+            false => None,
+        };
 
         let layers = r.read_byte()?;
         let pattern_width = r.read_byte()?;
         let pattern_height = r.read_byte()?;
-        let pattern_depth = r.read_byte()?;
+        let pattern_depth = match profile.pattern_depth {
+            true => r.read_byte()?,
+            false => 1,
+        };
 
         let animation_length = r.read_byte()?;
 
-        if animation_length > 1 {
-            let _async = r.read_byte()? == 0;
-            let _loop_count = r.read_i32()?;
-            let _start_phase = r.read_byte()?;
-
-            for _ in 0..animation_length {
-                let _min = r.read_u32()?;
-                let _max = r.read_u32()?;
+        let animation = match animation_length > 1 && profile.enhanced_animations {
+            true => {
+                let mode = r.read_byte()?;
+                let loop_count = r.read_i32()?;
+                let start_phase = r.read_byte()?;
+
+                let durations = (0..animation_length)
+                    .map(|_| Ok((r.read_u32()?, r.read_u32()?)))
+                    .collect::<io::Result<_>>()?;
+
+                Some(Animation {
+                    mode,
+                    loop_count,
+                    start_phase,
+                    durations,
+                })
             }
-        }
+            false => None,
+        };
 
-        let sprite_count = width as u16
-            * height as u16
-            * pattern_width as u16
-            * pattern_height as u16
-            * pattern_depth as u16
-            * layers as u16
-            * animation_length as u16;
+        let sprite_count = width as usize
+            * height as usize
+            * pattern_width as usize
+            * pattern_height as usize
+            * pattern_depth as usize
+            * layers as usize
+            * animation_length as usize;
 
-        let mut sprite_ids = Vec::with_capacity(sprite_count as usize);
+        let mut sprite_ids = Vec::with_capacity(sprite_count);
 
         for _ in 0..sprite_count {
-            sprite_ids.push(r.read_u32()?);
+            sprite_ids.push(match profile.extended {
+                true => r.read_u32()?,
+                false => r.read_u16()? as u32,
+            });
         }
 
         Ok(Thing {
+            flags: Vec::new(),
+
             width,
             height,
+            exact_size,
             layers,
 
             pattern_width,
@@ -177,28 +336,68 @@ impl Thing {
             displacement,
             elevation,
 
+            animation_length,
+            animation,
             sprite_ids,
+
+            group_type: None,
+            groups: Vec::new(),
         })
     }
 }
 
 impl DatContainer {
+    /// Reads a dat file, with the rules of the client its signature belongs
+    /// to, or those of the newest client if it is unknown.
     pub fn new(r: &mut dyn io::Read) -> io::Result<DatContainer> {
         let signature = r.read_u32()?;
+        let profile = Client::from_dat_signature(signature)
+            .map_or_else(Profile::default, |client| client.profile);
+
+        DatContainer::read(r, signature, profile)
+    }
+
+    pub fn with_profile(r: &mut dyn io::Read, profile: Profile) -> io::Result<DatContainer> {
+        let signature = r.read_u32()?;
 
+        DatContainer::read(r, signature, profile)
+    }
+
+    fn read(r: &mut dyn io::Read, signature: u32, profile: Profile) -> io::Result<DatContainer> {
+        // The highest id of each category
         let num_items = r.read_u16()?;
-        let _num_creatures = r.read_u16()?;
-        let _num_magic_effects = r.read_u16()?;
-        let _num_distance_effects = r.read_u16()?;
+        let num_creatures = r.read_u16()?;
+        let num_magic_effects = r.read_u16()?;
+        let num_distance_effects = r.read_u16()?;
+
+        let mut items = Vec::with_capacity(num_items.saturating_sub(99) as usize);
 
-        let mut client_id = 100;
-        let mut items = Vec::with_capacity((num_items - client_id) as usize);
+        for _ in 100..=num_items {
+            items.push(Thing::deserialize(r, &profile)?);
+        }
+
+        let mut creatures = Vec::with_capacity(num_creatures as usize);
 
-        while client_id <= num_items {
-            items.push(Thing::deserialize(r)?);
-            client_id += 1;
+        for _ in 1..=num_creatures {
+            creatures.push(Thing::deserialize_outfit(r, &profile)?);
         }
 
-        Ok(DatContainer { signature, items })
+        let mut read_things = |count: u16| -> io::Result<Vec<Thing>> {
+            (1..=count)
+                .map(|_| Thing::deserialize(r, &profile))
+                .collect()
+        };
+
+        let effects = read_things(num_magic_effects)?;
+        let missiles = read_things(num_distance_effects)?;
+
+        Ok(DatContainer {
+            signature,
+            profile,
+            items,
+            creatures,
+            effects,
+            missiles,
+        })
     }
 }
@@ -1,8 +1,10 @@
-use byteorder::{LittleEndian, ReadBytesExt};
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use std::fs::{self, File};
 use std::io::{self, Result};
+use std::path::{Path, PathBuf};
 
 use encoding::all::WINDOWS_1252;
-use encoding::{DecoderTrap, Encoding};
+use encoding::{DecoderTrap, EncoderTrap, Encoding};
 
 use crate::opentibia::Position;
 
@@ -27,21 +29,35 @@ pub trait ReadExt: io::Read {
         ReadBytesExt::read_i32::<LittleEndian>(self)
     }
 
+    fn read_u64(&mut self) -> Result<u64> {
+        ReadBytesExt::read_u64::<LittleEndian>(self)
+    }
+
     fn read_f32(&mut self) -> Result<f32> {
         ReadBytesExt::read_f32::<LittleEndian>(self)
     }
 
+    fn read_f64(&mut self) -> Result<f64> {
+        ReadBytesExt::read_f64::<LittleEndian>(self)
+    }
+
     fn read_string(&mut self) -> Result<String> {
         let length = self.read_u16()? as usize;
         self.read_fixed_string(length)
     }
 
+    fn read_long_string(&mut self) -> Result<String> {
+        let length = self.read_u32()? as usize;
+        self.read_fixed_string(length)
+    }
+
     fn read_fixed_string(&mut self, length: usize) -> Result<String> {
         let mut data = vec![0; length];
         self.read_exact(&mut data)?;
 
-        // FIXME: error handling
-        Ok(WINDOWS_1252.decode(&data, DecoderTrap::Strict).unwrap())
+        WINDOWS_1252
+            .decode(&data, DecoderTrap::Strict)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
     }
 
     fn read_position(&mut self) -> Result<Position> {
@@ -50,3 +66,96 @@ pub trait ReadExt: io::Read {
 }
 
 impl<R: io::Read + ?Sized> ReadExt for R {}
+
+pub trait WriteExt: io::Write {
+    fn write_byte(&mut self, v: u8) -> Result<()> {
+        WriteBytesExt::write_u8(self, v)
+    }
+
+    fn write_u16(&mut self, v: u16) -> Result<()> {
+        WriteBytesExt::write_u16::<LittleEndian>(self, v)
+    }
+
+    fn write_i16(&mut self, v: i16) -> Result<()> {
+        WriteBytesExt::write_i16::<LittleEndian>(self, v)
+    }
+
+    fn write_u32(&mut self, v: u32) -> Result<()> {
+        WriteBytesExt::write_u32::<LittleEndian>(self, v)
+    }
+
+    fn write_i32(&mut self, v: i32) -> Result<()> {
+        WriteBytesExt::write_i32::<LittleEndian>(self, v)
+    }
+
+    fn write_u64(&mut self, v: u64) -> Result<()> {
+        WriteBytesExt::write_u64::<LittleEndian>(self, v)
+    }
+
+    fn write_f32(&mut self, v: f32) -> Result<()> {
+        WriteBytesExt::write_f32::<LittleEndian>(self, v)
+    }
+
+    fn write_f64(&mut self, v: f64) -> Result<()> {
+        WriteBytesExt::write_f64::<LittleEndian>(self, v)
+    }
+
+    fn write_string(&mut self, s: &str) -> Result<()> {
+        let data = encode_string(s)?;
+
+        if data.len() > u16::MAX as usize {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "string too long",
+            ));
+        }
+
+        self.write_u16(data.len() as u16)?;
+        self.write_all(&data)
+    }
+
+    fn write_long_string(&mut self, s: &str) -> Result<()> {
+        let data = encode_string(s)?;
+
+        if data.len() > u32::MAX as usize {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "string too long",
+            ));
+        }
+
+        self.write_u32(data.len() as u32)?;
+        self.write_all(&data)
+    }
+
+    fn write_position(&mut self, pos: &Position) -> Result<()> {
+        pos.serialize(self)
+    }
+}
+
+impl<W: io::Write + ?Sized> WriteExt for W {}
+
+fn encode_string(s: &str) -> Result<Vec<u8>> {
+    WINDOWS_1252
+        .encode(s, EncoderTrap::Strict)
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "unencodable string"))
+}
+
+/// Writes a file with `write` into a temporary file next to `path` that then
+/// replaces it, so a failed write leaves the old file as it was.
+pub fn replace_file<F>(path: &Path, write: F) -> Result<()>
+where
+    F: FnOnce(File) -> Result<()>,
+{
+    let mut temp_path = path.as_os_str().to_owned();
+    temp_path.push(".tmp");
+    let temp_path = PathBuf::from(temp_path);
+
+    match File::create(&temp_path).and_then(write) {
+        Ok(()) => fs::rename(&temp_path, path),
+        Err(err) => {
+            let _ = fs::remove_file(&temp_path);
+            Err(err)
+        }
+    }
+}
@@ -0,0 +1,14 @@
+#[macro_use]
+extern crate glium;
+
+pub mod assets;
+pub mod clientversion;
+pub mod compression;
+pub mod datcontainer;
+pub mod helpers;
+pub mod map;
+pub mod opentibia;
+pub mod renderer;
+pub mod rootwindow;
+pub mod spriteatlas;
+pub mod spritecontainer;
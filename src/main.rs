@@ -1,31 +1,25 @@
-#[macro_use]
-extern crate glium;
-
 use std::time::Instant;
 
-mod datcontainer;
-mod helpers;
-mod map;
-mod opentibia;
-mod renderer;
-mod rootwindow;
-mod spriteatlas;
-mod spritecontainer;
-
 use std::fs::File;
-use std::io::Read;
+use std::io::{self, Read};
 
 use serde::Deserialize;
 
 use glium::glutin;
 
-use datcontainer::DatContainer;
-use renderer::Renderer;
-use rootwindow::RootWindow;
-use spritecontainer::SpriteContainer;
+use mapeditor::assets;
+use mapeditor::clientversion::{Client, Profile, CLIENTS};
+use mapeditor::compression;
+use mapeditor::datcontainer::DatContainer;
+use mapeditor::map::Map;
+use mapeditor::renderer::Renderer;
+use mapeditor::rootwindow::{self, RootWindow};
+use mapeditor::spritecontainer::SpriteContainer;
 
-use helpers::ReadExt;
-use opentibia::itemtypes;
+use mapeditor::helpers::ReadExt;
+use mapeditor::opentibia::dump::{self, DumpOptions, Format};
+use mapeditor::opentibia::map::AreaPager;
+use mapeditor::opentibia::{self, itemtypes};
 
 #[derive(Deserialize)]
 struct Config {
@@ -33,38 +27,270 @@ struct Config {
     dat: String,
     otb: String,
     map: String,
+
+    // Item names and other server side data
+    items_xml: Option<String>,
+
+    // Client version of the spr and dat, e.g. "8.60", instead of detecting
+    // it from their signatures
+    client_version: Option<String>,
+    // Parsing rules for a client without a known version, taking precedence
+    // over client_version
+    client_profile: Option<Profile>,
+
+    // Optional map filters: [min_x, min_y, max_x, max_y] and a list of floors
+    region: Option<[u16; 4]>,
+    floors: Option<Vec<u8>>,
+
+    // Load map areas as they come into view instead of all at startup.
+    // Compressed maps are always loaded completely.
+    lazy: Option<bool>,
 }
 
-fn main() {
+const DUMP_USAGE: &str = "usage: mapeditor dump [--json] [--depth N] [--kind KIND]... FILE";
+
+/// Prints the node tree of an OTB or OTBM file.
+fn dump_command(args: &[String]) -> Result<(), String> {
+    let mut options = DumpOptions::default();
+    let mut path = None;
+    let mut args = args.iter();
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--json" => options.format = Format::Json,
+            "--depth" => {
+                let depth = args.next().and_then(|depth| depth.parse().ok());
+                options.max_depth = Some(depth.ok_or(DUMP_USAGE)?);
+            }
+            "--kind" => options.kinds.push(args.next().ok_or(DUMP_USAGE)?.clone()),
+            _ if path.is_none() && !arg.starts_with("--") => path = Some(arg),
+            _ => return Err(DUMP_USAGE.into()),
+        }
+    }
+
+    let path = path.ok_or(DUMP_USAGE)?;
+    let data = compression::read_file(path).map_err(|err| format!("{}: {}", path, err))?;
+
+    let stdout = io::stdout();
+    let w = io::BufWriter::new(stdout.lock());
+
+    dump::dump(&data, &options, w).map_err(|err| format!("{}: {}", path, err))
+}
+
+const CHECK_USAGE: &str =
+    "usage: mapeditor check [--client VERSION] [--spr FILE] [--dat FILE] [--otb FILE]";
+
+/// Reports mismatches between the spr, dat and otb files, which default to
+/// the ones in the config.
+fn check_command(args: &[String]) -> Result<(), String> {
+    let (mut spr, mut dat, mut otb, mut client) = (None, None, None, None);
+    let mut custom_profile = None;
+    let mut args = args.iter();
+
+    while let Some(arg) = args.next() {
+        let path = match arg.as_str() {
+            "--client" => &mut client,
+            "--spr" => &mut spr,
+            "--dat" => &mut dat,
+            "--otb" => &mut otb,
+            _ => return Err(CHECK_USAGE.into()),
+        };
+
+        *path = Some(args.next().ok_or(CHECK_USAGE)?.clone());
+    }
+
+    if spr.is_none() || dat.is_none() || otb.is_none() {
+        let config = load_config()?;
+        spr.get_or_insert(config.spr);
+        dat.get_or_insert(config.dat);
+        otb.get_or_insert(config.otb);
+
+        if let Some(version) = config.client_version {
+            client.get_or_insert(version);
+        }
+
+        custom_profile = config.client_profile;
+    }
+
+    let (spr, dat, otb) = (spr.unwrap(), dat.unwrap(), otb.unwrap());
+    let profile = match custom_profile {
+        Some(profile) if client.is_none() => Some(profile),
+        _ => client_profile(client.as_deref())?,
+    };
+
+    let num_sprites = File::open(&spr)
+        .and_then(|f| open_spr(io::BufReader::new(f), profile))
+        .map_err(|err| format!("{}: {}", spr, err))?
+        .num_sprites;
+    let dat_items = File::open(&dat)
+        .and_then(|f| open_dat(&mut io::BufReader::new(f), profile))
+        .map_err(|err| format!("{}: {}", dat, err))?;
+    let otb_items = load_otb(&otb).map_err(|err| format!("{}: {}", otb, err))?;
+
+    let issues = assets::check(&otb_items, &dat_items, num_sprites);
+
+    for issue in &issues {
+        println!(
+            "{}: {}",
+            if issue.is_error() { "error" } else { "note" },
+            issue
+        );
+    }
+
+    match issues.iter().filter(|issue| issue.is_error()).count() {
+        0 => Ok(()),
+        errors => Err(format!("{} errors", errors)),
+    }
+}
+
+fn load_config() -> Result<Config, String> {
     let mut raw_config = String::new();
     File::open("conf.toml")
         .and_then(|mut f| f.read_to_string(&mut raw_config))
-        .unwrap();
+        .map_err(|e| format!("Failed to read conf.toml: {}", e))?;
+
+    toml::from_str(&raw_config).map_err(|e| format!("Failed to load config: {}", e))
+}
+
+/// The parsing rules of a client version, or `None` to detect them from
+/// the file signatures.
+fn client_profile(version: Option<&str>) -> Result<Option<Profile>, String> {
+    let version = match version {
+        Some(version) => version,
+        None => return Ok(None),
+    };
+
+    match Client::named(version) {
+        Some(client) => Ok(Some(client.profile)),
+        None => {
+            let known: Vec<_> = CLIENTS.iter().map(|client| client.name).collect();
+            Err(format!(
+                "unknown client version {}, known versions are {}",
+                version,
+                known.join(", ")
+            ))
+        }
+    }
+}
+
+fn open_spr<R>(r: R, profile: Option<Profile>) -> io::Result<SpriteContainer<R>>
+where
+    R: io::Read + io::Seek,
+{
+    let spr = match profile {
+        Some(profile) => SpriteContainer::with_profile(r, profile)?,
+        None => SpriteContainer::new(r)?,
+    };
+
+    if profile.is_none() && Client::from_spr_signature(spr.signature).is_none() {
+        warn_unknown_signature("spr", spr.signature);
+    }
+
+    Ok(spr)
+}
 
-    let config: Config = match toml::from_str(&raw_config) {
+fn open_dat(r: &mut dyn io::Read, profile: Option<Profile>) -> io::Result<DatContainer> {
+    let dat = match profile {
+        Some(profile) => DatContainer::with_profile(r, profile)?,
+        None => DatContainer::new(r)?,
+    };
+
+    if profile.is_none() && Client::from_dat_signature(dat.signature).is_none() {
+        warn_unknown_signature("dat", dat.signature);
+    }
+
+    Ok(dat)
+}
+
+fn warn_unknown_signature(file: &str, signature: u32) {
+    println!(
+        "warning: unknown {} signature {:08X}, reading it like the newest client; \
+         set client_version or client_profile in the config if it fails to load",
+        file, signature
+    );
+}
+
+/// Loads an items.otb file, possibly compressed.
+fn load_otb(path: &str) -> io::Result<itemtypes::Container> {
+    let data = compression::read_file(path)?;
+    let mut r = &data[..];
+    let _version = r.read_u32()?;
+
+    itemtypes::Container::from_slice(r)
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+
+    let command = match args.first().map(String::as_str) {
+        Some("dump") => Some(dump_command as fn(&[String]) -> Result<(), String>),
+        Some("check") => Some(check_command as _),
+        _ => None,
+    };
+
+    if let Some(command) = command {
+        if let Err(err) = command(&args[1..]) {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        }
+
+        return;
+    }
+
+    let config = match load_config() {
+        Ok(v) => v,
+        Err(e) => {
+            println!("{}", e);
+            return;
+        }
+    };
+
+    let profile = match config.client_profile {
+        Some(profile) => Ok(Some(profile)),
+        None => client_profile(config.client_version.as_deref()),
+    };
+
+    let profile = match profile {
         Ok(v) => v,
         Err(e) => {
-            println!("Failed to load config: {}", e);
+            println!("{}", e);
             return;
         }
     };
 
     // spr
     let spr_data = std::io::BufReader::new(File::open(config.spr).unwrap());
-    let spr = SpriteContainer::new(spr_data).unwrap();
+    let spr = open_spr(spr_data, profile).unwrap();
 
     // dat
     let mut data = std::io::BufReader::new(File::open(config.dat).unwrap());
-    let dat = DatContainer::new(&mut data).unwrap();
+    let dat = open_dat(&mut data, profile).unwrap();
 
     // otb
-    let mut data = std::io::BufReader::new(File::open(config.otb).unwrap());
-    let _version = data.read_u32().unwrap();
-    let otb = itemtypes::Container::new(&mut data).unwrap();
+    let mut otb = load_otb(&config.otb).unwrap();
+
+    if let Some(path) = &config.items_xml {
+        let warnings =
+            opentibia::itemsxml::load_file(path, &mut otb).expect("failed to load items.xml");
+
+        for warning in &warnings {
+            println!("warning: {}: {}", path, warning);
+        }
+    }
+
+    let issues = assets::check(&otb, &dat, spr.num_sprites);
+    let unreferenced = issues.iter().filter(|issue| !issue.is_error()).count();
+
+    for issue in issues.iter().filter(|issue| issue.is_error()) {
+        println!("warning: {}", issue);
+    }
 
-    // otbm
-    let mut data = std::io::BufReader::new(File::open(config.map).unwrap());
-    let _version = data.read_u32().unwrap();
+    if unreferenced > 0 {
+        println!(
+            "{} client ids are not used by any item, run `mapeditor check` for a list",
+            unreferenced
+        );
+    }
 
     // let node = Node::deserialize(&mut data, false).unwrap();
     // let node = opentibia::binaryfile::streaming_parser(&mut data, false,
@@ -73,28 +299,73 @@ fn main() {
     //    });
 
     let start = Instant::now();
-    let mut map = map::Map::new();
 
-    let mut otbm_map = opentibia::map::Loader::open(&mut data).unwrap();
-    let mut tiles = 0;
+    let options = opentibia::map::LoadOptions {
+        lenient: true,
+        region: config
+            .region
+            .map(|[min_x, min_y, max_x, max_y]| opentibia::map::Region {
+                min_x,
+                min_y,
+                max_x,
+                max_y,
+            }),
+        floors: config.floors,
+    };
+
+    if config.lazy == Some(true) {
+        let map = AreaPager::open(&config.map, options, &otb).expect("failed to open OTBM");
+
+        if map.is_fully_loaded() {
+            println!(
+                "OTBM load took {:.2}ms, compressed maps can't be loaded on demand",
+                start.elapsed().as_secs_f64() * 1000.
+            );
+        } else {
+            println!(
+                "OTBM open took {:.2}ms, areas are loaded on demand",
+                start.elapsed().as_secs_f64() * 1000.
+            );
+        }
+
+        return run(dat, otb, map, spr);
+    }
 
-    otbm_map
-        .load(&mut data, |ref pos, items| {
-            tiles += 1;
+    // otbm, possibly compressed with gzip or xz
+    let data = compression::read_file(&config.map).unwrap();
 
-            let sec = map.get_or_create(pos);
-            sec.get_tile(pos).extend_from_slice(items);
-        })
+    let mut otbm_map = opentibia::map::Loader::open_with_options(&data[..], options).unwrap();
+    otbm_map.set_item_types(&otb);
+
+    let threads = std::thread::available_parallelism().map_or(1, |n| n.get());
+
+    let map = otbm_map
+        .load_parallel(&data, threads)
         .expect("failed to load OTBM");
 
+    let tiles = map.tiles().count();
     let dur = start.elapsed().as_secs_f64();
 
+    for warning in &otbm_map.warnings {
+        println!("warning: {}", warning);
+    }
+
     println!(
-        "OTBM node load took {:.2}ms for {} tiles",
+        "OTBM node load took {:.2}ms for {} tiles ({} areas skipped)",
         dur * 1000.,
-        tiles
+        tiles,
+        otbm_map.skipped_areas
     );
 
+    run(dat, otb, map, spr);
+}
+
+fn run(
+    dat: DatContainer,
+    otb: itemtypes::Container,
+    map: Map,
+    spr: SpriteContainer<io::BufReader<File>>,
+) {
     let event_loop = glutin::event_loop::EventLoop::new();
     let window = glutin::window::WindowBuilder::new()
         .with_title("Map Editor")
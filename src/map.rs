@@ -1,27 +1,121 @@
 use std::collections::hash_map::Entry;
 use std::collections::HashMap;
+use std::error;
+use std::fmt;
+use std::io;
 
-use crate::opentibia::map::Item;
+use crate::opentibia::map::{AreaPager, MapHeader, Spawn, Tile, Town, Waypoint};
 use crate::opentibia::Position;
 
 #[derive(Debug, Default)]
 pub struct Map {
+    header: MapHeader,
     sectors: HashMap<Position, Sector>,
+    towns: Vec<Town>,
+    waypoints: Vec<Waypoint>,
+    spawns: Vec<Spawn>,
+    // Loads the tiles of maps opened with `AreaPager::open` as they are needed
+    pager: Option<AreaPager>,
 }
 
+/// Reasons an edit to the header, towns, waypoints or spawns of a map was
+/// rejected.
+#[derive(Clone, Debug, PartialEq)]
+pub enum EditError {
+    DuplicateTown {
+        id: u32,
+    },
+    UnknownTown {
+        id: u32,
+    },
+    DuplicateWaypoint {
+        name: String,
+    },
+    UnknownWaypoint {
+        name: String,
+    },
+    UnknownSpawn {
+        center: Position,
+    },
+    /// The position is beyond the width or height of the map, or below
+    /// the lowest floor.
+    OutsideMap {
+        position: Position,
+    },
+    IncompatibleItemsVersion {
+        items_version: (u32, u32),
+        loaded: (u32, u32),
+    },
+}
+
+impl fmt::Display for EditError {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match self {
+            EditError::DuplicateTown { id } => write!(fmt, "town {} already exists", id),
+            EditError::UnknownTown { id } => write!(fmt, "no town with id {}", id),
+            EditError::DuplicateWaypoint { name } => {
+                write!(fmt, "waypoint {:?} already exists", name)
+            }
+            EditError::UnknownWaypoint { name } => write!(fmt, "no waypoint named {:?}", name),
+            EditError::UnknownSpawn { center } => write!(fmt, "no spawn at {}", center),
+            EditError::OutsideMap { position } => {
+                write!(fmt, "position {} is outside of the map", position)
+            }
+            EditError::IncompatibleItemsVersion {
+                items_version,
+                loaded,
+            } => write!(
+                fmt,
+                "items version {}.{} is not compatible with the loaded items {}.{}",
+                items_version.0, items_version.1, loaded.0, loaded.1
+            ),
+        }
+    }
+}
+
+impl error::Error for EditError {}
+
 #[derive(Debug)]
 pub struct Sector {
     pub origin: Position,
-    pub tiles: Vec<Vec<Item>>,
+    pub tiles: Vec<Tile>,
 }
 
 impl Map {
+    pub const LOWEST_FLOOR: u8 = 15;
+
     pub fn new() -> Map {
         Map {
             ..Default::default()
         }
     }
 
+    pub fn header(&self) -> &MapHeader {
+        &self.header
+    }
+
+    pub fn header_mut(&mut self) -> &mut MapHeader {
+        &mut self.header
+    }
+
+    /// Sets the width and height in the header to just fit all tiles. Tiles
+    /// still left in the file of a map loaded on demand are paged in first.
+    pub fn update_size(&mut self) -> io::Result<()> {
+        self.page_in_all()?;
+
+        let (width, height) = self.tiles().fold((0, 0), |(width, height), (pos, _)| {
+            (
+                u16::max(width, pos.x.saturating_add(1)),
+                u16::max(height, pos.y.saturating_add(1)),
+            )
+        });
+
+        self.header.width = width;
+        self.header.height = height;
+
+        Ok(())
+    }
+
     pub fn get(&self, pos: &Position) -> Option<&Sector> {
         let sector_pos = Sector::get_sector_pos(pos);
         self.sectors.get(&sector_pos)
@@ -32,6 +126,240 @@ impl Map {
         self.sectors.get_mut(&sector_pos)
     }
 
+    pub fn get_tile(&self, pos: &Position) -> Option<&Tile> {
+        self.get(pos)
+            .map(|sector| &sector.tiles[Sector::tile_index(pos)])
+    }
+
+    pub fn get_tile_mut(&mut self, pos: &Position) -> Option<&mut Tile> {
+        self.get_mut(pos).map(|sector| sector.get_tile(pos))
+    }
+
+    /// Iterates over all non-empty tiles, in no particular order.
+    pub fn tiles(&self) -> impl Iterator<Item = (Position, &Tile)> {
+        self.sectors()
+            .flat_map(|sector| sector.iter())
+            .filter(|(_, tile)| !tile.is_empty())
+    }
+
+    /// Iterates over all tiles belonging to the given house.
+    pub fn house_tiles(&self, house_id: u32) -> impl Iterator<Item = (Position, &Tile)> {
+        self.tiles()
+            .filter(move |(_, tile)| tile.house_id == Some(house_id))
+    }
+
+    /// Moves all non-empty tiles of `other` into this map.
+    pub fn merge(&mut self, other: Map) {
+        for (sector_pos, sector) in other.sectors {
+            match self.sectors.entry(sector_pos) {
+                Entry::Vacant(v) => {
+                    v.insert(sector);
+                }
+
+                Entry::Occupied(mut o) => {
+                    let tiles = o.get_mut().tiles.iter_mut().zip(sector.tiles);
+
+                    for (tile, other_tile) in tiles {
+                        if !other_tile.is_empty() {
+                            *tile = other_tile;
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn pager(&self) -> Option<&AreaPager> {
+        self.pager.as_ref()
+    }
+
+    pub fn set_pager(&mut self, pager: AreaPager) {
+        self.pager = Some(pager);
+    }
+
+    /// Loads the tiles of the sector containing `pos` if the map is loaded on
+    /// demand and they aren't loaded yet. Tiles that already hold something,
+    /// e.g. ones edited before, are kept as they are.
+    pub fn page_in(&mut self, pos: &Position) -> io::Result<()> {
+        self.with_pager(|pager, map| pager.load(map, Sector::get_sector_pos(pos), Sector::SIZE))
+    }
+
+    /// Loads all tiles still left in the file, as needed before saving.
+    pub fn page_in_all(&mut self) -> io::Result<()> {
+        self.with_pager(|pager, map| pager.load_all(map))
+    }
+
+    /// Whether all tiles of the map are loaded. Tiles still left in the file
+    /// are not returned by `tiles` and the like.
+    pub fn is_fully_loaded(&self) -> bool {
+        self.pager.as_ref().is_none_or(|pager| pager.is_complete())
+    }
+
+    fn with_pager<F>(&mut self, f: F) -> io::Result<()>
+    where
+        F: FnOnce(&mut AreaPager, &mut Map) -> io::Result<()>,
+    {
+        match self.pager.take() {
+            Some(mut pager) => {
+                let result = f(&mut pager, self);
+                self.pager = Some(pager);
+                result
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Checks `position` against the size in the header, so positions
+    /// without a tile (or whose tiles aren't paged in yet) are accepted.
+    fn check_inside(&self, position: Position) -> Result<(), EditError> {
+        let inside = position.x < self.header.width
+            && position.y < self.header.height
+            && position.z <= Map::LOWEST_FLOOR;
+
+        match inside {
+            true => Ok(()),
+            false => Err(EditError::OutsideMap { position }),
+        }
+    }
+
+    pub fn towns(&self) -> impl Iterator<Item = &Town> {
+        self.towns.iter()
+    }
+
+    pub fn get_town(&self, id: u32) -> Option<&Town> {
+        self.towns.iter().find(|town| town.id == id)
+    }
+
+    fn get_town_mut(&mut self, id: u32) -> Result<&mut Town, EditError> {
+        self.towns
+            .iter_mut()
+            .find(|town| town.id == id)
+            .ok_or(EditError::UnknownTown { id })
+    }
+
+    pub fn add_town(&mut self, town: Town) -> Result<(), EditError> {
+        if self.get_town(town.id).is_some() {
+            return Err(EditError::DuplicateTown { id: town.id });
+        }
+
+        self.check_inside(town.temple_position)?;
+        self.towns.push(town);
+
+        Ok(())
+    }
+
+    pub fn rename_town(&mut self, id: u32, name: &str) -> Result<(), EditError> {
+        self.get_town_mut(id)?.name = name.to_string();
+        Ok(())
+    }
+
+    pub fn move_town(&mut self, id: u32, temple_position: Position) -> Result<(), EditError> {
+        self.check_inside(temple_position)?;
+        self.get_town_mut(id)?.temple_position = temple_position;
+
+        Ok(())
+    }
+
+    pub fn remove_town(&mut self, id: u32) -> Result<Town, EditError> {
+        match self.towns.iter().position(|town| town.id == id) {
+            Some(index) => Ok(self.towns.remove(index)),
+            None => Err(EditError::UnknownTown { id }),
+        }
+    }
+
+    pub fn waypoints(&self) -> impl Iterator<Item = &Waypoint> {
+        self.waypoints.iter()
+    }
+
+    pub fn get_waypoint(&self, name: &str) -> Option<&Waypoint> {
+        self.waypoints.iter().find(|waypoint| waypoint.name == name)
+    }
+
+    fn get_waypoint_mut(&mut self, name: &str) -> Result<&mut Waypoint, EditError> {
+        self.waypoints
+            .iter_mut()
+            .find(|waypoint| waypoint.name == name)
+            .ok_or_else(|| EditError::UnknownWaypoint {
+                name: name.to_string(),
+            })
+    }
+
+    pub fn add_waypoint(&mut self, waypoint: Waypoint) -> Result<(), EditError> {
+        if self.get_waypoint(&waypoint.name).is_some() {
+            return Err(EditError::DuplicateWaypoint {
+                name: waypoint.name,
+            });
+        }
+
+        self.check_inside(waypoint.position)?;
+        self.waypoints.push(waypoint);
+
+        Ok(())
+    }
+
+    pub fn rename_waypoint(&mut self, name: &str, new_name: &str) -> Result<(), EditError> {
+        if name != new_name && self.get_waypoint(new_name).is_some() {
+            return Err(EditError::DuplicateWaypoint {
+                name: new_name.to_string(),
+            });
+        }
+
+        self.get_waypoint_mut(name)?.name = new_name.to_string();
+        Ok(())
+    }
+
+    pub fn move_waypoint(&mut self, name: &str, position: Position) -> Result<(), EditError> {
+        self.check_inside(position)?;
+        self.get_waypoint_mut(name)?.position = position;
+
+        Ok(())
+    }
+
+    pub fn remove_waypoint(&mut self, name: &str) -> Result<Waypoint, EditError> {
+        match self.waypoints.iter().position(|w| w.name == name) {
+            Some(index) => Ok(self.waypoints.remove(index)),
+            None => Err(EditError::UnknownWaypoint {
+                name: name.to_string(),
+            }),
+        }
+    }
+
+    pub fn spawns(&self) -> impl Iterator<Item = &Spawn> {
+        self.spawns.iter()
+    }
+
+    pub fn add_spawn(&mut self, spawn: Spawn) -> Result<(), EditError> {
+        self.check_inside(spawn.center)?;
+        self.spawns.push(spawn);
+
+        Ok(())
+    }
+
+    /// Removes the first spawn centered at `center`.
+    pub fn remove_spawn(&mut self, center: Position) -> Result<Spawn, EditError> {
+        match self.spawns.iter().position(|spawn| spawn.center == center) {
+            Some(index) => Ok(self.spawns.remove(index)),
+            None => Err(EditError::UnknownSpawn { center }),
+        }
+    }
+
+    /// Adds towns, waypoints and spawns read from a file as they are, since
+    /// the tiles they point at may not have been loaded.
+    pub(crate) fn extend_unchecked(
+        &mut self,
+        towns: Vec<Town>,
+        waypoints: Vec<Waypoint>,
+        spawns: Vec<Spawn>,
+    ) {
+        self.towns.extend(towns);
+        self.waypoints.extend(waypoints);
+        self.spawns.extend(spawns);
+    }
+
+    pub fn sectors(&self) -> impl Iterator<Item = &Sector> {
+        self.sectors.values()
+    }
+
     pub fn get_or_create(&mut self, pos: &Position) -> &mut Sector {
         let sector_pos = Sector::get_sector_pos(pos);
 
@@ -50,7 +378,7 @@ impl Sector {
         let mut tiles = Vec::with_capacity(Sector::NUM_TILES);
 
         for _ in 0..Sector::NUM_TILES {
-            tiles.push(Vec::new());
+            tiles.push(Tile::default());
         }
 
         Sector { origin, tiles }
@@ -64,11 +392,15 @@ impl Sector {
         }
     }
 
-    pub fn get_tile(&mut self, pos: &Position) -> &mut Vec<Item> {
-        &mut self.tiles[((pos.x % Sector::SIZE) * Sector::SIZE + (pos.y % Sector::SIZE)) as usize]
+    fn tile_index(pos: &Position) -> usize {
+        ((pos.x % Sector::SIZE) * Sector::SIZE + (pos.y % Sector::SIZE)) as usize
+    }
+
+    pub fn get_tile(&mut self, pos: &Position) -> &mut Tile {
+        &mut self.tiles[Sector::tile_index(pos)]
     }
 
-    pub fn iter(&self) -> SectorTileIterator {
+    pub fn iter(&self) -> SectorTileIterator<'_> {
         SectorTileIterator {
             sector: self,
             index: 0,
@@ -78,7 +410,7 @@ impl Sector {
 
 impl<'a> IntoIterator for &'a Sector {
     type IntoIter = SectorTileIterator<'a>;
-    type Item = (Position, &'a [Item]);
+    type Item = (Position, &'a Tile);
 
     fn into_iter(self) -> Self::IntoIter {
         SectorTileIterator {
@@ -94,7 +426,7 @@ pub struct SectorTileIterator<'a> {
 }
 
 impl<'a> Iterator for SectorTileIterator<'a> {
-    type Item = (Position, &'a [Item]);
+    type Item = (Position, &'a Tile);
 
     fn next(&mut self) -> Option<Self::Item> {
         if self.index < Sector::NUM_TILES as u16 {
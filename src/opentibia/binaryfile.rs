@@ -1,5 +1,6 @@
-use crate::helpers::ReadExt;
+use crate::helpers::{ReadExt, WriteExt};
 use std::io;
+use std::ops::Range;
 
 #[derive(Debug)]
 pub struct Node {
@@ -9,9 +10,14 @@ pub struct Node {
 }
 
 impl Node {
-    const ESCAPE: u8 = 0xFD;
-    const START: u8 = 0xFE;
-    const END: u8 = 0xFF;
+    pub const ESCAPE: u8 = 0xFD;
+    pub const START: u8 = 0xFE;
+    pub const END: u8 = 0xFF;
+
+    fn needs_escape(b: u8) -> bool {
+        // The markers are the three highest byte values
+        b >= Node::ESCAPE
+    }
 
     pub fn deserialize(r: &mut dyn io::Read, skip_start: bool) -> io::Result<Node> {
         if !skip_start {
@@ -50,55 +56,495 @@ impl Node {
             children,
         })
     }
+
+    pub fn serialize(&self, w: &mut dyn io::Write) -> io::Result<()> {
+        let mut writer = NodeWriter::new(w);
+        self.write_node(&mut writer)
+    }
+
+    fn write_node<W>(&self, writer: &mut NodeWriter<W>) -> io::Result<()>
+    where
+        W: io::Write,
+    {
+        writer.begin(self.kind)?;
+        writer.write_data(&self.data)?;
+
+        for child in &self.children {
+            child.write_node(writer)?;
+        }
+
+        writer.end()
+    }
 }
 
-pub fn streaming_parser<R, F>(mut r: R, skip_start: bool, mut callback: F) -> io::Result<()>
+/// Writing counterpart to `EventParser`: nodes are opened with
+/// `begin`, filled with `write_data` (escaped as needed) and closed with `end`.
+pub struct NodeWriter<W> {
+    w: W,
+    depth: usize,
+}
+
+impl<W> NodeWriter<W>
 where
-    F: FnMut(u8, &[u8]) -> io::Result<bool>,
-    R: io::Read,
+    W: io::Write,
 {
-    if !skip_start {
-        let data = r.read_byte()?;
+    pub fn new(w: W) -> NodeWriter<W> {
+        NodeWriter { w, depth: 0 }
+    }
 
-        if data != Node::START {
+    pub fn begin(&mut self, kind: u8) -> io::Result<()> {
+        self.w.write_byte(Node::START)?;
+        self.w.write_byte(kind)?;
+        self.depth += 1;
+
+        Ok(())
+    }
+
+    pub fn write_data(&mut self, data: &[u8]) -> io::Result<()> {
+        if self.depth == 0 {
             return Err(io::Error::new(
                 io::ErrorKind::InvalidInput,
-                "expected start of a node",
+                "node data written outside of a node",
             ));
         }
+
+        let mut data = data;
+
+        while let Some(i) = data.iter().position(|&b| Node::needs_escape(b)) {
+            self.w.write_all(&data[..i])?;
+            self.w.write_byte(Node::ESCAPE)?;
+            self.w.write_byte(data[i])?;
+
+            data = &data[i + 1..];
+        }
+
+        self.w.write_all(data)
+    }
+
+    pub fn end(&mut self) -> io::Result<()> {
+        if self.depth == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "unbalanced end of a node",
+            ));
+        }
+
+        self.depth -= 1;
+        self.w.write_byte(Node::END)
+    }
+
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    pub fn into_inner(self) -> W {
+        self.w
+    }
+}
+
+/// Location of a node within a file: the byte offset of its start marker (or
+/// end marker, when it is exited) and its nesting depth (the root node is at
+/// depth 0).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct NodePosition {
+    pub offset: u64,
+    pub depth: usize,
+}
+
+/// What a parser callback wants to happen after an event. Skipping only
+/// applies to `NodeEvent::Enter`; the skipped node's `Exit` is still emitted.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ParseAction {
+    Continue,
+    /// Skip over the children of the node without decoding them.
+    SkipChildren,
+    Stop,
+}
+
+/// Event passed to the callback of an `EventParser`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NodeEvent<'a> {
+    /// A node starts. Its data is complete, its children (if any) follow.
+    Enter { kind: u8, data: &'a [u8] },
+    /// The innermost open node ends.
+    Exit { kind: u8 },
+}
+
+/// Walks a node tree, emitting an `Enter` event for each node and an `Exit`
+/// event once all of its children are done. The callback also gets the
+/// position of the node's start (or end) marker and the kinds of the nodes
+/// enclosing it, outermost first.
+///
+/// Parsing can be stopped from the callback and resumed later with another
+/// call to `parse` or `parse_slice`, which continues at `offset`.
+#[derive(Clone, Debug, Default)]
+pub struct EventParser {
+    // Offset of the next byte to read from the file
+    offset: u64,
+    path: Vec<u8>,
+    // Marker ending the data of the last node, read but not handled yet
+    pending: Option<u8>,
+    buffer: Vec<u8>,
+}
+
+impl EventParser {
+    /// Creates a parser for the node starting at `offset` in the file, inside
+    /// of nodes with the kinds in `path`.
+    pub fn new(offset: u64, path: Vec<u8>) -> EventParser {
+        EventParser {
+            offset,
+            path,
+            ..Default::default()
+        }
+    }
+
+    /// Offset in the file where parsing continues.
+    pub fn offset(&self) -> u64 {
+        self.offset - self.pending.is_some() as u64
+    }
+
+    /// Kinds of the nodes currently open, outermost first.
+    pub fn path(&self) -> &[u8] {
+        &self.path
     }
 
-    let mut kind = r.read_byte()?;
-    let mut data = Vec::new();
+    pub fn depth(&self) -> usize {
+        self.path.len()
+    }
+
+    /// Parses nodes from `r`, which has to be located at `offset`, until the
+    /// end of the reader or until the callback returns `ParseAction::Stop`.
+    /// Nodes opened during the call have to end before the end of the reader.
+    pub fn parse<R, F>(&mut self, mut r: R, mut callback: F) -> io::Result<()>
+    where
+        F: FnMut(NodePosition, &[u8], NodeEvent) -> io::Result<ParseAction>,
+        R: io::Read,
+    {
+        let start_depth = self.depth();
+
+        loop {
+            let marker = match self.pending.take() {
+                Some(marker) => marker,
+                None => match r.read_byte() {
+                    Ok(b) => {
+                        self.offset += 1;
+                        b
+                    }
+                    Err(ref err) if err.kind() == io::ErrorKind::UnexpectedEof => {
+                        return self.end_of_data(start_depth)
+                    }
+                    Err(err) => return Err(err),
+                },
+            };
+
+            let action = match marker {
+                Node::START => {
+                    let position = self.position();
+
+                    let kind = r.read_byte()?;
+                    self.offset += 1;
+                    self.buffer.clear();
+
+                    // The node's data runs until the next marker or EOF
+                    loop {
+                        let b = match r.read_byte() {
+                            Ok(b) => b,
+                            Err(ref err) if err.kind() == io::ErrorKind::UnexpectedEof => break,
+                            Err(err) => return Err(err),
+                        };
+
+                        self.offset += 1;
+
+                        match b {
+                            Node::START | Node::END => {
+                                self.pending = Some(b);
+                                break;
+                            }
+                            Node::ESCAPE => {
+                                self.buffer.push(r.read_byte()?);
+                                self.offset += 1;
+                            }
+                            _ => self.buffer.push(b),
+                        }
+                    }
+
+                    let event = NodeEvent::Enter {
+                        kind,
+                        data: &self.buffer,
+                    };
+
+                    let action = callback(position, &self.path, event)?;
+                    self.path.push(kind);
+
+                    if action == ParseAction::SkipChildren && self.pending == Some(Node::START) {
+                        // Node kinds are never escaped
+                        r.read_byte()?;
+                        self.offset += 1;
+
+                        // The first child and the skipped node itself
+                        skip_nodes(&mut r, &mut self.offset, 2)?;
+                        self.pending = Some(Node::END);
+                    }
 
-    loop {
-        let b = match r.read_byte() {
-            Ok(b) => b,
-            Err(ref a) if a.kind() == io::ErrorKind::UnexpectedEof => {
-                // The last node is not yet processed at this point.
-                // We don't care about the result since this is at EOF
-                callback(kind, &data)?;
+                    action
+                }
+
+                Node::END => self.exit(&mut callback)?,
+                _ if self.path.is_empty() => return Err(expected_start()),
+
+                // Data following the children of a node
+                Node::ESCAPE => {
+                    r.read_byte()?;
+                    self.offset += 1;
+                    ParseAction::Continue
+                }
+                _ => ParseAction::Continue,
+            };
+
+            if action == ParseAction::Stop {
                 return Ok(());
             }
-            Err(err) => return Err(err),
-        };
+        }
+    }
 
-        match b {
-            Node::START => {
-                let callback_result = callback(kind, &data)?;
-                data.clear();
+    /// Same as `parse`, but reads the nodes directly out of `data`, the
+    /// complete file (e.g. memory-mapped), until its end. Node data without
+    /// escape bytes is passed to the callback as a slice of `data`; only
+    /// escaped data is copied.
+    pub fn parse_slice<F>(&mut self, data: &[u8], mut callback: F) -> io::Result<()>
+    where
+        F: FnMut(NodePosition, &[u8], NodeEvent) -> io::Result<ParseAction>,
+    {
+        let start_depth = self.depth();
 
-                // Stop parsing if callback returned false
-                if !callback_result {
-                    return Ok(());
+        loop {
+            let marker = match self.pending.take() {
+                Some(marker) => marker,
+                None => match data.get(self.offset as usize) {
+                    Some(&b) => {
+                        self.offset += 1;
+                        b
+                    }
+                    None => return self.end_of_data(start_depth),
+                },
+            };
+
+            let action = match marker {
+                Node::START => {
+                    let position = self.position();
+
+                    let mut i = self.offset as usize;
+                    let kind = *data.get(i).ok_or_else(unterminated_node)?;
+                    i += 1;
+
+                    // The node's data runs until the next marker or the end
+                    let data_start = i;
+                    let mut escaped = false;
+
+                    let data_end = loop {
+                        let j = match data[i..].iter().position(|&b| Node::needs_escape(b)) {
+                            Some(n) => i + n,
+                            None => data.len(),
+                        };
+
+                        if escaped {
+                            self.buffer.extend_from_slice(&data[i..j]);
+                        }
+
+                        if data.get(j) != Some(&Node::ESCAPE) {
+                            break j;
+                        }
+
+                        if !escaped {
+                            self.buffer.clear();
+                            self.buffer.extend_from_slice(&data[data_start..j]);
+                            escaped = true;
+                        }
+
+                        self.buffer
+                            .push(*data.get(j + 1).ok_or_else(unterminated_node)?);
+                        i = j + 2;
+                    };
+
+                    self.pending = data.get(data_end).copied();
+                    self.offset = (data_end + self.pending.is_some() as usize) as u64;
+
+                    let node_data = match escaped {
+                        true => &self.buffer[..],
+                        false => &data[data_start..data_end],
+                    };
+
+                    let event = NodeEvent::Enter {
+                        kind,
+                        data: node_data,
+                    };
+
+                    let action = callback(position, &self.path, event)?;
+                    self.path.push(kind);
+
+                    if action == ParseAction::SkipChildren && self.pending == Some(Node::START) {
+                        // Node kinds are never escaped
+                        let mut r = data
+                            .get(self.offset as usize + 1..)
+                            .ok_or_else(unterminated_node)?;
+                        self.offset += 1;
+
+                        skip_nodes(&mut r, &mut self.offset, 2)?;
+                        self.pending = Some(Node::END);
+                    }
+
+                    action
+                }
+
+                Node::END => self.exit(&mut callback)?,
+                _ if self.path.is_empty() => return Err(expected_start()),
+
+                // Data following the children of a node
+                Node::ESCAPE => {
+                    self.offset += 1;
+                    ParseAction::Continue
                 }
+                _ => ParseAction::Continue,
+            };
+
+            if action == ParseAction::Stop {
+                return Ok(());
+            }
+        }
+    }
+
+    // Position of the marker that was just read
+    fn position(&self) -> NodePosition {
+        NodePosition {
+            offset: self.offset - 1,
+            depth: self.path.len(),
+        }
+    }
+
+    // A file cut off inside of a node would otherwise look like a complete
+    // one with fewer nodes
+    fn end_of_data(&self, start_depth: usize) -> io::Result<()> {
+        match self.depth() > start_depth {
+            true => Err(unterminated_node()),
+            false => Ok(()),
+        }
+    }
+
+    fn exit<F>(&mut self, callback: &mut F) -> io::Result<ParseAction>
+    where
+        F: FnMut(NodePosition, &[u8], NodeEvent) -> io::Result<ParseAction>,
+    {
+        let kind = self.path.pop().ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "unbalanced end of a node")
+        })?;
+
+        callback(self.position(), &self.path, NodeEvent::Exit { kind })
+    }
+}
+
+fn unterminated_node() -> io::Error {
+    io::Error::new(io::ErrorKind::UnexpectedEof, "unterminated node")
+}
 
-                kind = r.read_byte()?;
+fn expected_start() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, "expected start of a node")
+}
+
+/// Skips raw bytes until `open` more nodes have ended than started.
+fn skip_nodes<R>(mut r: R, offset: &mut u64, mut open: usize) -> io::Result<()>
+where
+    R: io::Read,
+{
+    while open > 0 {
+        let b = r.read_byte()?;
+        *offset += 1;
+
+        match b {
+            Node::START => {
+                // Node kinds are never escaped
+                r.read_byte()?;
+                *offset += 1;
+                open += 1;
+            }
+            Node::END => open -= 1,
+            Node::ESCAPE => {
+                r.read_byte()?;
+                *offset += 1;
             }
+            _ => (),
+        }
+    }
+
+    Ok(())
+}
+
+/// Calls `callback` with the kind and data of each node, in file order,
+/// until it returns false. `skip_start` is set if the start marker of the
+/// first node has already been read from `r`.
+pub fn streaming_parser<R, F>(r: R, skip_start: bool, mut callback: F) -> io::Result<()>
+where
+    F: FnMut(u8, &[u8]) -> io::Result<bool>,
+    R: io::Read,
+{
+    let mut parser = EventParser::default();
 
-            Node::END => (),
-            Node::ESCAPE => data.push(r.read_byte()?),
-            _ => data.push(b),
+    if skip_start {
+        parser.offset = 1;
+        parser.pending = Some(Node::START);
+    }
+
+    parser.parse(r, |_, _, event| match event {
+        NodeEvent::Enter { kind, data } => Ok(match callback(kind, data)? {
+            true => ParseAction::Continue,
+            false => ParseAction::Stop,
+        }),
+        NodeEvent::Exit { .. } => Ok(ParseAction::Continue),
+    })
+}
+
+/// Kind of a node and its byte range, start to end marker inclusive.
+pub type NodeRange = (u8, Range<usize>);
+
+/// Finds the node starting at `start` in `data` and all of its following
+/// siblings, returning the range of each along with the offset the scan
+/// stopped at: the end marker of the parent node or the end of `data`. Node
+/// contents are scanned but not decoded.
+pub fn sibling_node_ranges(data: &[u8], start: usize) -> io::Result<(Vec<NodeRange>, usize)> {
+    let mut ranges = Vec::new();
+    let mut i = start;
+
+    // Stop at the end of the parent node or the end of the data
+    while i < data.len() && data[i] != Node::END {
+        if data[i] != Node::START {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "expected start of a node",
+            ));
         }
+
+        let node_start = i;
+        let kind = *data.get(i + 1).ok_or_else(unterminated_node)?;
+        let mut depth = 1;
+        i += 2;
+
+        while depth > 0 {
+            match *data.get(i).ok_or_else(unterminated_node)? {
+                // Skip the kind byte, which is never escaped
+                Node::START => {
+                    depth += 1;
+                    i += 2;
+                }
+                Node::ESCAPE => i += 2,
+                Node::END => {
+                    depth -= 1;
+                    i += 1;
+                }
+                _ => i += 1,
+            }
+        }
+
+        ranges.push((kind, node_start..i));
     }
+
+    Ok((ranges, i))
 }
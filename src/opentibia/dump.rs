@@ -0,0 +1,372 @@
+//! Writes out the node tree of an OTB or OTBM file, with the data of known
+//! nodes decoded, for looking into files that fail to load.
+
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::io;
+
+use num::FromPrimitive;
+
+use super::binaryfile::{EventParser, NodeEvent, ParseAction};
+use super::itemtypes;
+use super::map::{Item, MapError, MapHeader, Monster, NodeKind, Spawn, Tile, Town, Waypoint};
+use super::Position;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Format {
+    /// One line per node, indented by depth
+    #[default]
+    Text,
+    /// An array of node objects, with their children nested in them
+    Json,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FileKind {
+    Otb,
+    Otbm,
+}
+
+impl FileKind {
+    /// Tells the files apart by their root node, whose data starts with flags
+    /// and a 140 byte version attribute in OTB files. `data` is the complete
+    /// file.
+    pub fn detect(data: &[u8]) -> FileKind {
+        let mut file_kind = FileKind::Otbm;
+
+        let _ = EventParser::new(4, Vec::new()).parse_slice(data, |_, _, event| {
+            if let NodeEvent::Enter { data, .. } = event {
+                if data.get(4..7) == Some(&[1, 140, 0]) {
+                    file_kind = FileKind::Otb;
+                }
+            }
+
+            Ok(ParseAction::Stop)
+        });
+
+        file_kind
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct DumpOptions {
+    pub format: Format,
+    /// Deepest nodes written, the root node being at depth 0
+    pub max_depth: Option<usize>,
+    /// Names or numbers of the node kinds to write, each along with its
+    /// children. Everything is written if empty.
+    pub kinds: Vec<String>,
+}
+
+/// Writes the nodes of `data`, the complete file, to `w`. Nodes written
+/// before a parse error are kept, followed by the error. JSON output stays
+/// valid, with the error as an `{"error": ...}` object after the nodes of
+/// the innermost node left open.
+///
+/// Counts stored without an attribute in version 0 maps can't be told apart
+/// without item types, so such items are decoded as if they had none.
+pub fn dump<W>(data: &[u8], options: &DumpOptions, w: W) -> io::Result<()>
+where
+    W: io::Write,
+{
+    let mut dumper = Dumper {
+        w,
+        options,
+        file_kind: FileKind::detect(data),
+        area: None,
+        written: Vec::new(),
+        first_child: vec![true],
+    };
+
+    if options.format == Format::Json {
+        dumper.w.write_all(b"[")?;
+    }
+
+    let result =
+        EventParser::new(4, Vec::new()).parse_slice(data, |position, path, event| match event {
+            NodeEvent::Enter { kind, data } => dumper.enter(position.offset, path, kind, data),
+            NodeEvent::Exit { .. } => {
+                dumper.exit()?;
+                Ok(ParseAction::Continue)
+            }
+        });
+
+    if options.format == Format::Json {
+        if let Err(err) = &result {
+            dumper.write_json_error(err)?;
+        }
+
+        dumper.w.write_all(b"\n]\n")?;
+    }
+
+    dumper.w.flush()?;
+    result
+}
+
+struct Dumper<'a, W> {
+    w: W,
+    options: &'a DumpOptions,
+    file_kind: FileKind,
+
+    // Origin of the last TileArea, for the positions of its tiles
+    area: Option<Position>,
+
+    // Whether each open node was written
+    written: Vec<bool>,
+    // Whether the next node written in each open JSON array is its first
+    first_child: Vec<bool>,
+}
+
+impl<W> Dumper<'_, W>
+where
+    W: io::Write,
+{
+    fn enter(
+        &mut self,
+        offset: u64,
+        path: &[u8],
+        kind: u8,
+        data: &[u8],
+    ) -> io::Result<ParseAction> {
+        let depth = path.len();
+        let name = self.kind_name(depth, kind);
+        let decoded = self.decode(offset, depth, kind, data);
+
+        let write = self.options.kinds.is_empty()
+            || self.written.last() == Some(&true)
+            || self.matches(kind, name.as_deref());
+
+        self.written.push(write);
+
+        if write {
+            let name = name.as_deref().unwrap_or("Unknown");
+
+            match self.options.format {
+                Format::Text => {
+                    let indent = depth * 2;
+
+                    write!(
+                        self.w,
+                        "{:indent$}{} [0x{:02X}] @{} len={}",
+                        "",
+                        name,
+                        kind,
+                        offset,
+                        data.len(),
+                        indent = indent
+                    )?;
+
+                    if let Value::Object(fields) = &decoded {
+                        for (key, value) in fields {
+                            if !is_empty(value) {
+                                write!(self.w, " {}={}", key, value)?;
+                            }
+                        }
+                    }
+
+                    writeln!(self.w)?;
+                }
+
+                Format::Json => {
+                    let node = json!({
+                        "kind": kind,
+                        "name": name,
+                        "offset": offset,
+                        "length": data.len(),
+                        "decoded": decoded,
+                    });
+
+                    // Children are written as they are parsed, so the object
+                    // is left open
+                    let mut node = serde_json::to_string(&node)?;
+                    node.pop();
+
+                    let indent = self.first_child.len() * 2;
+                    let first = self.first_child.last_mut().expect("no open JSON array");
+                    let comma = if *first { "" } else { "," };
+                    *first = false;
+
+                    write!(
+                        self.w,
+                        "{}\n{:indent$}{},\"children\":[",
+                        comma,
+                        "",
+                        node,
+                        indent = indent
+                    )?;
+
+                    self.first_child.push(true);
+                }
+            }
+        }
+
+        match self.options.max_depth {
+            Some(max_depth) if depth >= max_depth => Ok(ParseAction::SkipChildren),
+            _ => Ok(ParseAction::Continue),
+        }
+    }
+
+    fn exit(&mut self) -> io::Result<()> {
+        if self.written.pop() == Some(true) && self.options.format == Format::Json {
+            self.first_child.pop();
+            self.w.write_all(b"]}")?;
+        }
+
+        Ok(())
+    }
+
+    // Ends the JSON output at a parse error: the error is the last element of
+    // the innermost open array, after which the open nodes are closed
+    fn write_json_error(&mut self, err: &io::Error) -> io::Result<()> {
+        let error = json!({ "error": err.to_string() });
+        let indent = self.first_child.len() * 2;
+        let first = self.first_child.last().expect("no open JSON array");
+        let comma = if *first { "" } else { "," };
+
+        write!(
+            self.w,
+            "{}\n{:indent$}{}",
+            comma,
+            "",
+            error,
+            indent = indent
+        )?;
+
+        // The top-level array is closed by `dump`
+        while self.first_child.len() > 1 {
+            self.first_child.pop();
+            self.w.write_all(b"]}")?;
+        }
+
+        Ok(())
+    }
+
+    fn matches(&self, kind: u8, name: Option<&str>) -> bool {
+        self.options.kinds.iter().any(|wanted| {
+            wanted.parse() == Ok(kind) || name.is_some_and(|name| name.eq_ignore_ascii_case(wanted))
+        })
+    }
+
+    fn kind_name(&self, depth: usize, kind: u8) -> Option<String> {
+        match self.file_kind {
+            FileKind::Otbm => NodeKind::from_u8(kind).map(|kind| format!("{:?}", kind)),
+            FileKind::Otb if depth == 0 => Some("Root".into()),
+            FileKind::Otb if depth == 1 => Some("Item".into()),
+            FileKind::Otb => None,
+        }
+    }
+
+    fn decode(&mut self, offset: u64, depth: usize, kind: u8, data: &[u8]) -> Value {
+        let decoded = match self.file_kind {
+            FileKind::Otbm => self.decode_otbm(offset, kind, data),
+            FileKind::Otb => decode_otb(depth, kind, data),
+        };
+
+        decoded.unwrap_or_else(|err| json!({ "error": err.to_string() }))
+    }
+
+    fn decode_otbm(&mut self, offset: u64, kind: u8, mut data: &[u8]) -> io::Result<Value> {
+        let kind = match NodeKind::from_u8(kind) {
+            Some(kind) => kind,
+            None => return Ok(Value::Null),
+        };
+
+        match kind {
+            NodeKind::Root => {
+                let mut header = MapHeader::default();
+                header.read_root_data(data)?;
+
+                Ok(json!({
+                    "version": header.version,
+                    "width": header.width,
+                    "height": header.height,
+                    "items_version": header.items_version(),
+                }))
+            }
+
+            NodeKind::MapData => {
+                let mut header = MapHeader::default();
+                header.read_map_data(data)?;
+
+                Ok(json!({
+                    "description": header.description,
+                    "house_file": header.house_file,
+                    "spawn_file": header.spawn_file,
+                    "unknown_attribute": header.unknown_attribute,
+                }))
+            }
+
+            NodeKind::TileArea => {
+                let origin = Position::deserialize(&mut data)?;
+                self.area = Some(origin);
+
+                to_value(origin)
+            }
+
+            NodeKind::Tile | NodeKind::HouseTile => {
+                let (area_offset, tile) = Tile::deserialize(kind, data)?;
+                let mut value = to_value(tile)?;
+
+                let position = match self.area {
+                    Some(origin) => Some(Tile::position(origin, area_offset).ok_or(
+                        MapError::TileOutsideMap {
+                            offset,
+                            kind,
+                            origin,
+                            area_offset,
+                        },
+                    )?),
+                    None => None,
+                };
+
+                value["area_offset"] = json!(area_offset);
+                value["position"] = to_value(position)?;
+
+                Ok(value)
+            }
+
+            NodeKind::Item => to_value(Item::deserialize(data, |_| false)?),
+            NodeKind::SpawnArea => to_value(Spawn::deserialize(data)?),
+            NodeKind::Monster => to_value(Monster::deserialize(data)?),
+            NodeKind::Town => to_value(Town::deserialize(data)?),
+            NodeKind::WayPoint => to_value(Waypoint::deserialize(data)?),
+
+            _ => Ok(Value::Null),
+        }
+    }
+}
+
+fn decode_otb(depth: usize, kind: u8, data: &[u8]) -> io::Result<Value> {
+    match depth {
+        0 => {
+            let mut container = itemtypes::Container::default();
+            container.load_header(data)?;
+
+            Ok(json!({
+                "flags": container.flags,
+                "version": container.version,
+                "description": container.description,
+            }))
+        }
+
+        1 => to_value(itemtypes::Item::deserialize(kind, data)?),
+        _ => Ok(Value::Null),
+    }
+}
+
+fn to_value<T>(value: T) -> io::Result<Value>
+where
+    T: Serialize,
+{
+    Ok(serde_json::to_value(value)?)
+}
+
+// Left out of the text format to keep lines short
+fn is_empty(value: &Value) -> bool {
+    match value {
+        Value::Null => true,
+        Value::Array(values) => values.is_empty(),
+        Value::Object(fields) => fields.is_empty(),
+        _ => false,
+    }
+}
@@ -0,0 +1,273 @@
+use encoding::all::WINDOWS_1252;
+use encoding::{DecoderTrap, Encoding};
+use serde::Serialize;
+use std::error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use xml::attribute::OwnedAttribute;
+use xml::reader::{EventReader, XmlEvent};
+
+use super::itemtypes::Container;
+
+/// Server side data of an item type, from items.xml.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct ItemMetadata {
+    pub name: Option<String>,
+    pub article: Option<String>,
+    pub plural: Option<String>,
+    pub description: Option<String>,
+    /// The `type` attribute, e.g. container, door or magicfield
+    pub kind: Option<String>,
+    /// All other `<attribute>` tags, in file order
+    pub attributes: Vec<Attribute>,
+}
+
+/// An `<attribute key=".." value=".."/>` tag, which may have attribute tags
+/// of its own, e.g. the damage of a field.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct Attribute {
+    pub key: String,
+    pub value: String,
+    pub children: Vec<Attribute>,
+}
+
+impl Attribute {
+    pub fn get(&self, key: &str) -> Option<&Attribute> {
+        find_attribute(&self.children, key)
+    }
+}
+
+impl ItemMetadata {
+    /// Looks up an attribute by its key, ignoring case like the server does.
+    pub fn attribute(&self, key: &str) -> Option<&Attribute> {
+        find_attribute(&self.attributes, key)
+    }
+}
+
+fn find_attribute<'a>(attributes: &'a [Attribute], key: &str) -> Option<&'a Attribute> {
+    attributes
+        .iter()
+        .find(|attribute| attribute.key.eq_ignore_ascii_case(key))
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ItemsXmlWarning {
+    MissingFromOtb {
+        server_id: u16,
+    },
+    DuplicateEntry {
+        server_id: u16,
+    },
+    NameMismatch {
+        server_id: u16,
+        otb: String,
+        xml: String,
+    },
+}
+
+impl fmt::Display for ItemsXmlWarning {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match self {
+            ItemsXmlWarning::MissingFromOtb { server_id } => {
+                write!(fmt, "item {} is not in the OTB", server_id)
+            }
+            ItemsXmlWarning::DuplicateEntry { server_id } => {
+                write!(fmt, "item {} is defined more than once", server_id)
+            }
+            ItemsXmlWarning::NameMismatch {
+                server_id,
+                otb,
+                xml,
+            } => write!(
+                fmt,
+                "item {} is named {:?} in the OTB but {:?} in items.xml",
+                server_id, otb, xml
+            ),
+        }
+    }
+}
+
+impl error::Error for ItemsXmlWarning {}
+
+/// Reads items.xml and attaches its data to the item types with the same
+/// server ids. Entries are kept in file order, so the first definition of an
+/// id wins.
+pub fn load<R>(mut r: R, items: &mut Container) -> io::Result<Vec<ItemsXmlWarning>>
+where
+    R: io::Read,
+{
+    let mut data = Vec::new();
+    r.read_to_end(&mut data)?;
+
+    // Most files are Latin-1, whatever their declaration says, but the XML
+    // parser only reads UTF-8
+    let text = match String::from_utf8(data) {
+        Ok(text) => text,
+        Err(err) => WINDOWS_1252
+            .decode(err.as_bytes(), DecoderTrap::Replace)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?,
+    };
+
+    let mut loader = XmlLoader {
+        items,
+        warnings: Vec::new(),
+        entry: None,
+        attributes: Vec::new(),
+    };
+
+    for event in EventReader::new(text.as_bytes()) {
+        let event = event.map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+
+        match event {
+            XmlEvent::StartElement {
+                name, attributes, ..
+            } => loader.start(&name.local_name, &attributes)?,
+            XmlEvent::EndElement { name } => loader.end(&name.local_name),
+            _ => (),
+        }
+    }
+
+    Ok(loader.warnings)
+}
+
+pub fn load_file<P>(path: P, items: &mut Container) -> io::Result<Vec<ItemsXmlWarning>>
+where
+    P: AsRef<Path>,
+{
+    load(io::BufReader::new(fs::File::open(path)?), items)
+}
+
+struct XmlLoader<'a> {
+    items: &'a mut Container,
+    warnings: Vec<ItemsXmlWarning>,
+
+    // The item being read, with the range of its server ids
+    entry: Option<(u16, u16, ItemMetadata)>,
+    // Open attribute tags of the entry
+    attributes: Vec<Attribute>,
+}
+
+impl XmlLoader<'_> {
+    fn start(&mut self, name: &str, attributes: &[OwnedAttribute]) -> io::Result<()> {
+        let value = |key: &str| {
+            attributes
+                .iter()
+                .find(|attr| attr.name.local_name == key)
+                .map(|attr| attr.value.clone())
+        };
+
+        match name {
+            "item" => {
+                let id = |key: &str| -> io::Result<Option<u16>> {
+                    value(key)
+                        .map(|id| {
+                            id.trim().parse().map_err(|_| {
+                                io::Error::new(
+                                    io::ErrorKind::InvalidData,
+                                    format!("invalid item {} {:?}", key, id),
+                                )
+                            })
+                        })
+                        .transpose()
+                };
+
+                let (from, to) = match (id("id")?, id("fromid")?, id("toid")?) {
+                    (Some(id), _, _) => (id, id),
+                    (None, Some(from), Some(to)) if from <= to => (from, to),
+                    _ => {
+                        return Err(io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            "item without a valid id or id range",
+                        ))
+                    }
+                };
+
+                let metadata = ItemMetadata {
+                    name: value("name"),
+                    article: value("article"),
+                    plural: value("plural"),
+                    ..Default::default()
+                };
+
+                self.entry = Some((from, to, metadata));
+                self.attributes.clear();
+            }
+
+            "attribute" if self.entry.is_some() => self.attributes.push(Attribute {
+                key: value("key").unwrap_or_default(),
+                value: value("value").unwrap_or_default(),
+                children: Vec::new(),
+            }),
+
+            _ => (),
+        }
+
+        Ok(())
+    }
+
+    fn end(&mut self, name: &str) {
+        match name {
+            "attribute" => {
+                let Some(attribute) = self.attributes.pop() else {
+                    return;
+                };
+
+                if let Some(parent) = self.attributes.last_mut() {
+                    parent.children.push(attribute);
+                } else if let Some((_, _, metadata)) = &mut self.entry {
+                    match attribute.key.to_ascii_lowercase().as_str() {
+                        "description" => metadata.description = Some(attribute.value),
+                        "type" => metadata.kind = Some(attribute.value),
+                        "article" => metadata.article = Some(attribute.value),
+                        "plural" => metadata.plural = Some(attribute.value),
+                        _ => metadata.attributes.push(attribute),
+                    }
+                }
+            }
+
+            "item" => {
+                if let Some((from, to, metadata)) = self.entry.take() {
+                    for server_id in from..=to {
+                        self.attach(server_id, &metadata);
+                    }
+                }
+            }
+
+            _ => (),
+        }
+    }
+
+    fn attach(&mut self, server_id: u16, metadata: &ItemMetadata) {
+        let item = match self.items.items.get_mut(server_id as usize) {
+            Some(item) => item,
+            None => {
+                self.warnings
+                    .push(ItemsXmlWarning::MissingFromOtb { server_id });
+                return;
+            }
+        };
+
+        if item.metadata.is_some() {
+            self.warnings
+                .push(ItemsXmlWarning::DuplicateEntry { server_id });
+            return;
+        }
+
+        // Names in the OTB are NUL padded by some editors
+        let otb_name = item.name.as_deref().map(|name| name.trim_end_matches('\0'));
+
+        if let (Some(otb), Some(xml)) = (otb_name, &metadata.name) {
+            if !otb.is_empty() && otb != xml {
+                self.warnings.push(ItemsXmlWarning::NameMismatch {
+                    server_id,
+                    otb: otb.to_string(),
+                    xml: xml.clone(),
+                });
+            }
+        }
+
+        item.metadata = Some(metadata.clone());
+    }
+}
@@ -1,11 +1,17 @@
 use num::FromPrimitive;
 use num_derive::FromPrimitive;
-use std::io;
+use serde::Serialize;
+use std::error;
+use std::fmt;
+use std::io::{self, Write};
+use std::path::Path;
 use vec_map::VecMap;
 
-use crate::helpers::ReadExt;
+use crate::helpers::{self, ReadExt, WriteExt};
 
-use super::binaryfile;
+use super::binaryfile::{self, NodeEvent};
+use super::itemsxml::ItemMetadata;
+use super::RawAttribute;
 
 #[derive(Debug, FromPrimitive, PartialEq)]
 enum AttributeKind {
@@ -43,18 +49,477 @@ enum AttributeKind {
     WareId,
 }
 
+#[derive(Debug)]
+pub enum ItemTypeError {
+    DuplicateServerId { server_id: u16 },
+    UnknownServerId { server_id: u16 },
+    MismatchedServerId { key: usize, server_id: u16 },
+}
+
+impl fmt::Display for ItemTypeError {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match self {
+            ItemTypeError::DuplicateServerId { server_id } => {
+                write!(fmt, "duplicate item type with server id {}", server_id)
+            }
+            ItemTypeError::UnknownServerId { server_id } => {
+                write!(fmt, "no item type with server id {}", server_id)
+            }
+            ItemTypeError::MismatchedServerId { key, server_id } => write!(
+                fmt,
+                "item type with server id {} is stored as {}",
+                server_id, key
+            ),
+        }
+    }
+}
+
+impl error::Error for ItemTypeError {}
+
+impl From<ItemTypeError> for io::Error {
+    fn from(err: ItemTypeError) -> io::Error {
+        io::Error::new(io::ErrorKind::InvalidData, err)
+    }
+}
+
 #[derive(Debug, Default)]
 pub struct Container {
     pub flags: u32,
     pub version: (u32, u32, u32),
     pub description: String,
     pub items: VecMap<Item>,
+
+    // Attributes of the root node as read, with the version attribute
+    // written back from `version` and `description`. None for item types
+    // not read from a file, which get just a version attribute.
+    root_attributes: Option<Vec<RawAttribute>>,
 }
 
-#[derive(Debug, Default)]
+/// Kind of an item type, stored as the kind of its node. Most groups are
+/// deprecated and only kept for old files; newer files describe items with
+/// flags instead. Groups this editor doesn't know are kept as `Unknown`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize)]
+pub enum ItemGroup {
+    #[default]
+    None,
+    Ground,
+    Container,
+    Weapon,
+    Ammunition,
+    Armor,
+    Charges,
+    Teleport,
+    MagicField,
+    Writeable,
+    Key,
+    Splash,
+    Fluid,
+    Door,
+    Deprecated,
+    Unknown(u8),
+}
+
+impl ItemGroup {
+    /// Known groups, indexed by their node kind.
+    const KNOWN: [ItemGroup; 15] = [
+        ItemGroup::None,
+        ItemGroup::Ground,
+        ItemGroup::Container,
+        ItemGroup::Weapon,
+        ItemGroup::Ammunition,
+        ItemGroup::Armor,
+        ItemGroup::Charges,
+        ItemGroup::Teleport,
+        ItemGroup::MagicField,
+        ItemGroup::Writeable,
+        ItemGroup::Key,
+        ItemGroup::Splash,
+        ItemGroup::Fluid,
+        ItemGroup::Door,
+        ItemGroup::Deprecated,
+    ];
+
+    pub fn from_u8(raw: u8) -> ItemGroup {
+        ItemGroup::KNOWN
+            .get(raw as usize)
+            .copied()
+            .unwrap_or(ItemGroup::Unknown(raw))
+    }
+
+    pub fn to_u8(self) -> u8 {
+        match self {
+            ItemGroup::Unknown(raw) => raw,
+            known => ItemGroup::KNOWN
+                .iter()
+                .position(|&group| group == known)
+                .unwrap() as u8,
+        }
+    }
+}
+
+raw_flags! {
+    /// Raw OTB item flags.
+    ItemFlags
+}
+
+impl ItemFlags {
+    pub const BLOCK_SOLID: u32 = 1 << 0;
+    pub const BLOCK_PROJECTILE: u32 = 1 << 1;
+    pub const BLOCK_PATHFIND: u32 = 1 << 2;
+    pub const HAS_HEIGHT: u32 = 1 << 3;
+    pub const USEABLE: u32 = 1 << 4;
+    pub const PICKUPABLE: u32 = 1 << 5;
+    pub const MOVEABLE: u32 = 1 << 6;
+    pub const STACKABLE: u32 = 1 << 7;
+    pub const FLOOR_CHANGE_DOWN: u32 = 1 << 8;
+    pub const FLOOR_CHANGE_NORTH: u32 = 1 << 9;
+    pub const FLOOR_CHANGE_EAST: u32 = 1 << 10;
+    pub const FLOOR_CHANGE_SOUTH: u32 = 1 << 11;
+    pub const FLOOR_CHANGE_WEST: u32 = 1 << 12;
+    pub const ALWAYS_ON_TOP: u32 = 1 << 13;
+    pub const READABLE: u32 = 1 << 14;
+    pub const ROTATABLE: u32 = 1 << 15;
+    pub const HANGABLE: u32 = 1 << 16;
+    pub const VERTICAL: u32 = 1 << 17;
+    pub const HORIZONTAL: u32 = 1 << 18;
+    pub const CANNOT_DECAY: u32 = 1 << 19;
+    pub const ALLOW_DISTANCE_READ: u32 = 1 << 20;
+    pub const CLIENT_CHARGES: u32 = 1 << 22;
+    pub const LOOK_THROUGH: u32 = 1 << 23;
+    pub const ANIMATION: u32 = 1 << 24;
+    pub const FULL_TILE: u32 = 1 << 25;
+    pub const FORCE_USE: u32 = 1 << 26;
+
+    pub fn blocks_solid(self) -> bool {
+        self.contains(ItemFlags::BLOCK_SOLID)
+    }
+
+    pub fn blocks_projectile(self) -> bool {
+        self.contains(ItemFlags::BLOCK_PROJECTILE)
+    }
+
+    pub fn blocks_pathfind(self) -> bool {
+        self.contains(ItemFlags::BLOCK_PATHFIND)
+    }
+
+    pub fn has_height(self) -> bool {
+        self.contains(ItemFlags::HAS_HEIGHT)
+    }
+
+    pub fn pickupable(self) -> bool {
+        self.contains(ItemFlags::PICKUPABLE)
+    }
+
+    pub fn moveable(self) -> bool {
+        self.contains(ItemFlags::MOVEABLE)
+    }
+
+    pub fn stackable(self) -> bool {
+        self.contains(ItemFlags::STACKABLE)
+    }
+
+    pub fn always_on_top(self) -> bool {
+        self.contains(ItemFlags::ALWAYS_ON_TOP)
+    }
+
+    pub fn hangable(self) -> bool {
+        self.contains(ItemFlags::HANGABLE)
+    }
+
+    pub fn full_tile(self) -> bool {
+        self.contains(ItemFlags::FULL_TILE)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct Light {
+    pub level: u16,
+    pub color: u16,
+}
+
+/// Order of the attributes of an item type in the file it was read from, so
+/// they are written back the same way. It doesn't make item types differ.
+#[derive(Clone, Debug, Default)]
+pub struct AttributeOrder(Option<Vec<StoredAttribute>>);
+
+#[derive(Clone, Copy, Debug)]
+enum StoredAttribute {
+    Known(u8),
+    Unknown,
+}
+
+impl PartialEq for AttributeOrder {
+    fn eq(&self, _: &AttributeOrder) -> bool {
+        true
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
 pub struct Item {
     pub server_id: u16,
     pub client_id: Option<u16>,
+    pub group: ItemGroup,
+    pub flags: ItemFlags,
+
+    pub name: Option<String>,
+    pub description: Option<String>,
+    /// Walking speed on ground items
+    pub speed: Option<u16>,
+    /// MD5 of the item's sprites, to match it to the client files
+    pub sprite_hash: Option<[u8; 16]>,
+    pub minimap_color: Option<u16>,
+    pub max_read_write_chars: Option<u16>,
+    pub max_read_chars: Option<u16>,
+    pub light: Option<Light>,
+    /// Drawing order of items that are always on top: lower is drawn later
+    pub top_order: Option<u8>,
+    /// Item id shown in the market
+    pub ware_id: Option<u16>,
+
+    pub unknown_attributes: Vec<RawAttribute>,
+    #[serde(skip)]
+    pub attribute_order: AttributeOrder,
+
+    /// Names, descriptions and other server side data from items.xml
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<ItemMetadata>,
+}
+
+impl Item {
+    /// The name from items.xml, or the one in the OTB if there is none.
+    pub fn display_name(&self) -> Option<&str> {
+        self.metadata
+            .as_ref()
+            .and_then(|metadata| metadata.name.as_deref())
+            .or_else(|| self.name.as_deref().map(|name| name.trim_end_matches('\0')))
+    }
+
+    pub fn is_stackable(&self) -> bool {
+        self.flags.stackable()
+    }
+
+    pub fn is_splash(&self) -> bool {
+        self.group == ItemGroup::Splash
+    }
+
+    pub fn is_fluid_container(&self) -> bool {
+        self.group == ItemGroup::Fluid
+    }
+
+    pub(crate) fn deserialize(group: u8, mut data: &[u8]) -> io::Result<Item> {
+        let mut item = Item {
+            group: ItemGroup::from_u8(group),
+            flags: ItemFlags(data.read_u32()?),
+            ..Default::default()
+        };
+        let mut order = Vec::new();
+
+        while !data.is_empty() {
+            use self::AttributeKind::*;
+
+            let raw_kind = data.read_byte()?;
+            let len = data.read_u16()? as usize;
+
+            if data.len() < len {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "truncated item attribute",
+                ));
+            }
+
+            let (mut value, rest) = data.split_at(len);
+            data = rest;
+
+            order.push(StoredAttribute::Known(raw_kind));
+
+            // Attributes with an unexpected length or text that isn't
+            // Windows-1252 are kept as unknown ones
+            match (AttributeKind::from_u8(raw_kind), len) {
+                (Some(ServerId), 2) => item.server_id = value.read_u16()?,
+                (Some(ClientId), 2) => item.client_id = Some(value.read_u16()?),
+                (Some(Name), _) if item.name.is_none() && read_text(value).is_some() => {
+                    item.name = read_text(value)
+                }
+                (Some(Description), _)
+                    if item.description.is_none() && read_text(value).is_some() =>
+                {
+                    item.description = read_text(value)
+                }
+                (Some(Speed), 2) => item.speed = Some(value.read_u16()?),
+                (Some(SpriteHash), 16) => {
+                    let mut hash = [0; 16];
+                    hash.copy_from_slice(value);
+                    item.sprite_hash = Some(hash);
+                }
+                (Some(MinimapColor), 2) => item.minimap_color = Some(value.read_u16()?),
+                (Some(Attr07), 2) => item.max_read_write_chars = Some(value.read_u16()?),
+                (Some(Attr08), 2) => item.max_read_chars = Some(value.read_u16()?),
+                (Some(Light2), 4) => {
+                    item.light = Some(self::Light {
+                        level: value.read_u16()?,
+                        color: value.read_u16()?,
+                    });
+                }
+                (Some(TopOrder), 1) => item.top_order = Some(value.read_byte()?),
+                (Some(WareId), 2) => item.ware_id = Some(value.read_u16()?),
+
+                _ => {
+                    item.unknown_attributes.push(RawAttribute {
+                        kind: raw_kind,
+                        data: value.to_vec(),
+                    });
+                    *order.last_mut().unwrap() = StoredAttribute::Unknown;
+                }
+            }
+        }
+
+        item.attribute_order = AttributeOrder(Some(order));
+
+        Ok(item)
+    }
+
+    /// Writes the node data of the item, i.e. everything but its group.
+    /// Attributes are written in the order they were read in, followed by
+    /// new ones in the order of their kinds, like the item editor does.
+    pub(crate) fn serialize<W>(&self, mut w: W) -> io::Result<()>
+    where
+        W: io::Write,
+    {
+        use self::AttributeKind::*;
+
+        let mut attributes: Vec<(u8, Vec<u8>)> = Vec::new();
+        let mut add = |kind: AttributeKind, value: Vec<u8>| attributes.push((kind as u8, value));
+
+        // Files may leave out the server id, which is then 0
+        let had_server_id = match &self.attribute_order.0 {
+            Some(order) => order.iter().any(
+                |stored| matches!(stored, StoredAttribute::Known(kind) if *kind == ServerId as u8),
+            ),
+            None => true,
+        };
+
+        if had_server_id || self.server_id != 0 {
+            add(ServerId, self.server_id.to_le_bytes().to_vec());
+        }
+
+        if let Some(client_id) = self.client_id {
+            add(ClientId, client_id.to_le_bytes().to_vec());
+        }
+
+        for (kind, text) in [(Name, &self.name), (Description, &self.description)] {
+            if let Some(text) = text {
+                // Strings are already stored with a u16 length, like attributes
+                let mut value = Vec::new();
+                value.write_string(text)?;
+                add(kind, value.split_off(2));
+            }
+        }
+
+        if let Some(speed) = self.speed {
+            add(Speed, speed.to_le_bytes().to_vec());
+        }
+
+        if let Some(hash) = self.sprite_hash {
+            add(SpriteHash, hash.to_vec());
+        }
+
+        if let Some(color) = self.minimap_color {
+            add(MinimapColor, color.to_le_bytes().to_vec());
+        }
+
+        if let Some(chars) = self.max_read_write_chars {
+            add(Attr07, chars.to_le_bytes().to_vec());
+        }
+
+        if let Some(chars) = self.max_read_chars {
+            add(Attr08, chars.to_le_bytes().to_vec());
+        }
+
+        if let Some(light) = self.light {
+            let mut value = light.level.to_le_bytes().to_vec();
+            value.extend_from_slice(&light.color.to_le_bytes());
+            add(Light2, value);
+        }
+
+        if let Some(top_order) = self.top_order {
+            add(TopOrder, vec![top_order]);
+        }
+
+        if let Some(ware_id) = self.ware_id {
+            add(WareId, ware_id.to_le_bytes().to_vec());
+        }
+
+        let mut unknown = self
+            .unknown_attributes
+            .iter()
+            .map(|raw| (raw.kind, raw.data.clone()));
+
+        let attributes = match &self.attribute_order.0 {
+            Some(order) => {
+                let mut ordered = Vec::with_capacity(attributes.len());
+
+                for stored in order {
+                    match *stored {
+                        StoredAttribute::Known(kind) => {
+                            if let Some(i) = attributes.iter().position(|&(k, _)| k == kind) {
+                                ordered.push(attributes.remove(i));
+                            }
+                        }
+                        StoredAttribute::Unknown => ordered.extend(unknown.next()),
+                    }
+                }
+
+                // Attributes added since the item was read
+                ordered.extend(attributes);
+                ordered.extend(unknown);
+                ordered
+            }
+            None => {
+                attributes.extend(unknown);
+                attributes.sort_by_key(|&(kind, _)| kind);
+                attributes
+            }
+        };
+
+        w.write_u32(self.flags.0)?;
+
+        for (kind, value) in attributes {
+            write_attribute(&mut w, kind, &value)?;
+        }
+
+        Ok(())
+    }
+}
+
+fn read_text(mut data: &[u8]) -> Option<String> {
+    data.read_fixed_string(data.len()).ok()
+}
+
+/// Reads the description of the version attribute, up to its NUL.
+fn read_description(mut data: &[u8]) -> io::Result<String> {
+    let mut description = data.read_fixed_string(data.len())?;
+
+    if let Some(end) = description.find('\0') {
+        description.truncate(end);
+    }
+
+    Ok(description)
+}
+
+fn write_attribute<W>(mut w: W, kind: u8, value: &[u8]) -> io::Result<()>
+where
+    W: io::Write,
+{
+    if value.len() > u16::MAX as usize {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "item attribute too long",
+        ));
+    }
+
+    w.write_byte(kind)?;
+    w.write_u16(value.len() as u16)?;
+    w.write_all(value)
 }
 
 impl Container {
@@ -62,61 +527,240 @@ impl Container {
     where
         R: io::Read,
     {
-        let root_node = binaryfile::Node::deserialize(&mut r, false)?;
-        let mut data = &root_node.data[..];
+        let mut data = Vec::new();
+        r.read_to_end(&mut data)?;
 
+        Container::from_slice(&data)
+    }
+
+    /// Parses the item types out of `data`, e.g. a memory-mapped file,
+    /// starting at the root node.
+    pub fn from_slice(data: &[u8]) -> io::Result<Container> {
         let mut container = Container {
             ..Default::default()
         };
 
-        // currently not being used
-        container.flags = data.read_u32()?;
+        let mut parser = binaryfile::EventParser::default();
 
-        let attr = data.read_byte()?;
-        if attr == 1 {
-            let data_len = data.read_u16()?;
-            assert_eq!(data_len, 140);
+        parser.parse_slice(data, |_, path, event| {
+            // Item types are the children of the root node
+            match (event, path.len()) {
+                (NodeEvent::Enter { data, .. }, 0) => container.load_header(data)?,
+                (NodeEvent::Enter { kind, data }, 1) => {
+                    container.add(Item::deserialize(kind, data)?)?;
+                }
+                _ => (),
+            }
 
-            let major_version = data.read_u32()?;
-            let minor_version = data.read_u32()?;
-            let build = data.read_u32()?;
+            Ok(binaryfile::ParseAction::Continue)
+        })?;
 
-            container.version = (major_version, minor_version, build);
+        Ok(container)
+    }
 
-            let mut desc = data.read_fixed_string(128)?;
+    /// Writes the item types, starting at the root node. Like `new`, this
+    /// leaves out the file identifier.
+    pub fn serialize<W>(&self, w: W) -> io::Result<()>
+    where
+        W: io::Write,
+    {
+        let mut writer = binaryfile::NodeWriter::new(w);
+        let mut data = Vec::new();
 
-            if let Some(end) = desc.find('\0') {
-                desc.truncate(end);
-            }
+        data.write_u32(self.flags)?;
 
-            container.description = desc;
+        let version = self.version_data()?;
+        let root_attributes = self.root_attributes.iter().flatten();
+
+        // Files read without a version attribute only get one if a version
+        // or description has been set since
+        let has_version = root_attributes
+            .clone()
+            .any(|attribute| attribute.kind == Container::ATTR_VERSION);
+        let unversioned = self.root_attributes.is_some()
+            && self.version == (0, 0, 0)
+            && self.description.is_empty();
+
+        if !has_version && !unversioned {
+            write_attribute(&mut data, Container::ATTR_VERSION, &version)?;
         }
 
-        for item_node in &root_node.children {
-            let mut item = Item {
-                ..Default::default()
+        for attribute in root_attributes {
+            let value = match attribute.kind {
+                Container::ATTR_VERSION => &version,
+                _ => &attribute.data,
             };
 
-            let mut data = &item_node.data[..];
-            let _flags = data.read_u32()?;
+            write_attribute(&mut data, attribute.kind, value)?;
+        }
+
+        writer.begin(0)?;
+        writer.write_data(&data)?;
+
+        for (key, item) in &self.items {
+            if key != item.server_id as usize {
+                return Err(ItemTypeError::MismatchedServerId {
+                    key,
+                    server_id: item.server_id,
+                }
+                .into());
+            }
+
+            data.clear();
+            item.serialize(&mut data)?;
+
+            writer.begin(item.group.to_u8())?;
+            writer.write_data(&data)?;
+            writer.end()?;
+        }
+
+        writer.end()
+    }
+
+    /// Saves the item types to an items.otb file. They are written to a
+    /// temporary file that then replaces it, so a failed save leaves the
+    /// old file as it was.
+    pub fn save_file<P>(&self, path: P) -> io::Result<()>
+    where
+        P: AsRef<Path>,
+    {
+        helpers::replace_file(path.as_ref(), |file| {
+            let mut w = io::BufWriter::new(file);
 
-            while !data.is_empty() {
-                use self::AttributeKind::*;
+            // File identifier
+            w.write_u32(0)?;
+
+            self.serialize(&mut w)?;
+            w.flush()
+        })
+    }
 
-                let kind = AttributeKind::from_u8(data.read_byte()?).expect("unknown map node");
-                let len = data.read_u16()?;
+    /// Adds a new item type, which must not reuse a server id.
+    pub fn add(&mut self, item: Item) -> Result<(), ItemTypeError> {
+        let server_id = item.server_id;
 
-                match kind {
-                    ServerId => item.server_id = data.read_u16()?,
-                    ClientId => item.client_id = Some(data.read_u16()?),
+        if self.items.contains_key(server_id as usize) {
+            return Err(ItemTypeError::DuplicateServerId { server_id });
+        }
+
+        self.items.insert(server_id as usize, item);
+        Ok(())
+    }
 
-                    _ => data = &data[len as usize..],
+    pub fn remove(&mut self, server_id: u16) -> Option<Item> {
+        self.items.remove(server_id as usize)
+    }
+
+    pub fn set_client_id(
+        &mut self,
+        server_id: u16,
+        client_id: Option<u16>,
+    ) -> Result<(), ItemTypeError> {
+        let item = self
+            .items
+            .get_mut(server_id as usize)
+            .ok_or(ItemTypeError::UnknownServerId { server_id })?;
+
+        item.client_id = client_id;
+        Ok(())
+    }
+
+    /// The first server id after all existing ones, for custom item types.
+    pub fn next_server_id(&self) -> Option<u16> {
+        match self.items.keys().next_back() {
+            Some(id) => (id as u16).checked_add(1),
+            None => Some(100),
+        }
+    }
+
+    pub(crate) fn load_header(&mut self, mut data: &[u8]) -> io::Result<()> {
+        // currently not being used
+        self.flags = data.read_u32()?;
+
+        let mut attributes = Vec::new();
+
+        while !data.is_empty() {
+            let kind = data.read_byte()?;
+            let len = data.read_u16()? as usize;
+
+            if data.len() < len {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "truncated root attribute",
+                ));
+            }
+
+            let (value, rest) = data.split_at(len);
+            data = rest;
+
+            if kind == Container::ATTR_VERSION {
+                if len != Container::VERSION_LEN {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "unexpected length of the version attribute",
+                    ));
                 }
+
+                let mut version = value;
+                let major_version = version.read_u32()?;
+                let minor_version = version.read_u32()?;
+                let build = version.read_u32()?;
+
+                self.version = (major_version, minor_version, build);
+                self.description = read_description(version)?;
             }
 
-            container.items.insert(item.server_id as usize, item);
+            attributes.push(RawAttribute {
+                kind,
+                data: value.to_vec(),
+            });
         }
 
-        Ok(container)
+        self.root_attributes = Some(attributes);
+
+        Ok(())
+    }
+
+    const ATTR_VERSION: u8 = 1;
+    const VERSION_LEN: usize = 140;
+
+    /// The version attribute for `version` and `description`, keeping what
+    /// follows the description's NUL if the description is unchanged.
+    fn version_data(&self) -> io::Result<Vec<u8>> {
+        let mut data = Vec::with_capacity(Container::VERSION_LEN);
+        data.write_u32(self.version.0)?;
+        data.write_u32(self.version.1)?;
+        data.write_u32(self.version.2)?;
+
+        let read = self
+            .root_attributes
+            .iter()
+            .flatten()
+            .find(|attribute| attribute.kind == Container::ATTR_VERSION)
+            .map(|attribute| &attribute.data[12..]);
+
+        match read {
+            Some(description) if read_description(description)? == self.description => {
+                data.extend_from_slice(description);
+            }
+            _ => {
+                // NUL terminated and padded to 128 bytes
+                let mut description = Vec::new();
+                description.write_string(&self.description)?;
+                description.drain(..2);
+
+                if description.len() >= 128 {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        "item types description too long",
+                    ));
+                }
+
+                description.resize(128, 0);
+                data.extend_from_slice(&description);
+            }
+        }
+
+        Ok(data)
     }
 }
@@ -1,14 +1,24 @@
 use num::FromPrimitive;
 use num_derive::FromPrimitive;
-use std::io;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+use std::{error, fmt, io, mem, thread};
 
-use crate::helpers::ReadExt;
+use crate::compression::{self, Compression, Encoder, FileData, StreamDecoder};
+use crate::helpers::{self, ReadExt, WriteExt};
+use crate::map;
 
-use super::binaryfile;
-use super::Position;
+use super::binaryfile::{self, EventParser, NodeEvent, NodePosition, NodeWriter, ParseAction};
+use super::itemtypes;
+use super::{Position, RawAttribute};
 
-#[derive(Debug, FromPrimitive, PartialEq)]
-enum NodeKind {
+#[derive(Clone, Copy, Debug, FromPrimitive, PartialEq)]
+pub enum NodeKind {
     Root = 0,
     MapData = 2,
     ItemDef = 3,
@@ -65,13 +75,136 @@ enum NodeAttributeKind {
     AttributeMap = 128,
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
 pub struct Item {
     pub id: u16,
     pub attributes: Vec<ItemAttribute>,
+    /// Contents of containers
+    pub children: Vec<Item>,
 }
 
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct Tile {
+    pub flags: TileFlags,
+    pub house_id: Option<u32>,
+    pub items: Vec<Item>,
+    // Boxed since maps hold millions of tiles and hardly any of them have one
+    pub unknown_attribute: Option<Box<RawAttribute>>,
+}
+
+impl Tile {
+    /// Reads the data of a Tile or HouseTile node, returning the tile along
+    /// with its offset within the enclosing TileArea. Items stored as child
+    /// nodes are not included.
+    pub fn deserialize(kind: NodeKind, mut data: &[u8]) -> io::Result<((u8, u8), Tile)> {
+        let offset = (data.read_byte()?, data.read_byte()?);
+
+        // Most tiles hold a single item, their ground
+        let mut tile = Tile {
+            items: Vec::with_capacity(1),
+            ..Default::default()
+        };
+
+        if kind == NodeKind::HouseTile {
+            tile.house_id = Some(data.read_u32()?);
+        }
+
+        while !data.is_empty() {
+            use self::NodeAttributeKind::*;
+            let raw_attr = data.read_byte()?;
+
+            match NodeAttributeKind::from_u8(raw_attr) {
+                Some(TileFlags) => tile.flags = self::TileFlags(data.read_u32()?),
+
+                Some(Item) => {
+                    let item_id = data.read_u16()?;
+                    tile.items.push(self::Item {
+                        id: item_id,
+                        ..Default::default()
+                    });
+                }
+
+                _ => {
+                    tile.unknown_attribute = Some(Box::new(RawAttribute::read(raw_attr, &mut data)))
+                }
+            }
+        }
+
+        Ok((offset, tile))
+    }
+
+    /// Position of the tile at `offset` within the TileArea at `origin`, or
+    /// `None` if it is beyond the largest coordinates.
+    pub fn position(origin: Position, offset: (u8, u8)) -> Option<Position> {
+        Some(Position {
+            x: origin.x.checked_add(offset.0 as u16)?,
+            y: origin.y.checked_add(offset.1 as u16)?,
+            z: origin.z,
+        })
+    }
+
+    /// Whether the tile carries nothing worth storing.
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+            && self.flags.is_empty()
+            && self.house_id.is_none()
+            && self.unknown_attribute.is_none()
+    }
+}
+
+/// OTBM attributes carry no length, so an unknown one holds everything
+/// following it up to the end of its node. It is written back unchanged,
+/// after all known attributes.
+impl RawAttribute {
+    /// Takes the rest of `data`.
+    fn read(kind: u8, data: &mut &[u8]) -> RawAttribute {
+        let raw = RawAttribute {
+            kind,
+            data: data.to_vec(),
+        };
+
+        *data = &[];
+        raw
+    }
+
+    pub fn serialize<W>(&self, mut w: W) -> io::Result<()>
+    where
+        W: io::Write,
+    {
+        w.write_byte(self.kind)?;
+        w.write_all(&self.data)
+    }
+}
+
+raw_flags! {
+    /// Raw OTBM tile flags.
+    TileFlags
+}
+
+impl TileFlags {
+    pub const PROTECTION_ZONE: u32 = 1 << 0;
+    pub const NO_PVP: u32 = 1 << 2;
+    pub const NO_LOGOUT: u32 = 1 << 3;
+    pub const PVP_ZONE: u32 = 1 << 4;
+
+    pub fn protection_zone(self) -> bool {
+        self.contains(TileFlags::PROTECTION_ZONE)
+    }
+
+    pub fn no_pvp(self) -> bool {
+        self.contains(TileFlags::NO_PVP)
+    }
+
+    pub fn no_logout(self) -> bool {
+        self.contains(TileFlags::NO_LOGOUT)
+    }
+
+    pub fn pvp_zone(self) -> bool {
+        self.contains(TileFlags::PVP_ZONE)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
 pub enum ItemAttribute {
     Count(u8),
     ActionId(u16),
@@ -98,252 +231,1915 @@ pub enum ItemAttribute {
     Armor(i32),
     HitChance(u8),
     ShootRange(u8),
+    Custom(BTreeMap<String, CustomAttribute>),
+    Unknown(RawAttribute),
 }
 
-#[derive(Debug, Default)]
-pub struct Loader {
+/// Typed value stored in the attribute map used by newer servers for
+/// custom item attributes.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub enum CustomAttribute {
+    String(String),
+    Integer(i32),
+    Float(f32),
+    Boolean(bool),
+    Double(f64),
+}
+
+impl CustomAttribute {
+    const STRING: u8 = 1;
+    const INTEGER: u8 = 2;
+    const FLOAT: u8 = 3;
+    const BOOLEAN: u8 = 4;
+    const DOUBLE: u8 = 5;
+
+    /// Returns None for unknown types, whose values can't be skipped since
+    /// their length isn't known.
+    pub fn deserialize<R>(mut r: R) -> io::Result<Option<CustomAttribute>>
+    where
+        R: io::Read,
+    {
+        let value = match r.read_byte()? {
+            CustomAttribute::STRING => CustomAttribute::String(r.read_long_string()?),
+            CustomAttribute::INTEGER => CustomAttribute::Integer(r.read_i32()?),
+            CustomAttribute::FLOAT => CustomAttribute::Float(r.read_f32()?),
+            CustomAttribute::BOOLEAN => CustomAttribute::Boolean(r.read_byte()? != 0),
+            CustomAttribute::DOUBLE => CustomAttribute::Double(r.read_f64()?),
+            _ => return Ok(None),
+        };
+
+        Ok(Some(value))
+    }
+
+    pub fn serialize<W>(&self, mut w: W) -> io::Result<()>
+    where
+        W: io::Write,
+    {
+        match self {
+            CustomAttribute::String(v) => {
+                w.write_byte(CustomAttribute::STRING)?;
+                w.write_long_string(v)
+            }
+            CustomAttribute::Integer(v) => {
+                w.write_byte(CustomAttribute::INTEGER)?;
+                w.write_i32(*v)
+            }
+            CustomAttribute::Float(v) => {
+                w.write_byte(CustomAttribute::FLOAT)?;
+                w.write_f32(*v)
+            }
+            CustomAttribute::Boolean(v) => {
+                w.write_byte(CustomAttribute::BOOLEAN)?;
+                w.write_byte(*v as u8)
+            }
+            CustomAttribute::Double(v) => {
+                w.write_byte(CustomAttribute::DOUBLE)?;
+                w.write_f64(*v)
+            }
+        }
+    }
+}
+
+/// Errors raised while parsing an OTBM file. `offset` is the position of the
+/// offending node in the file.
+#[derive(Clone, Debug, PartialEq)]
+pub enum MapError {
+    UnknownNode {
+        offset: u64,
+        kind: u8,
+    },
+    UnexpectedNode {
+        offset: u64,
+        kind: NodeKind,
+    },
+    UnknownAttribute {
+        offset: u64,
+        kind: NodeKind,
+        position: Option<Position>,
+        attribute: u8,
+    },
+    TileOutsideTileArea {
+        offset: u64,
+        kind: NodeKind,
+    },
+    /// The tile's offset within its TileArea leads beyond the largest
+    /// coordinates.
+    TileOutsideMap {
+        offset: u64,
+        kind: NodeKind,
+        origin: Position,
+        area_offset: (u8, u8),
+    },
+    ItemOutsideTile {
+        offset: u64,
+    },
+    UnsupportedVersion {
+        version: u32,
+    },
+    ItemTypesRequired {
+        version: u32,
+    },
+    UnknownCustomAttributeType {
+        offset: u64,
+        position: Option<Position>,
+    },
+}
+
+impl fmt::Display for MapError {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match self {
+            MapError::UnknownNode { offset, kind } => {
+                write!(fmt, "unknown node kind {} at offset {}", kind, offset)
+            }
+            MapError::UnexpectedNode { offset, kind } => {
+                write!(fmt, "unexpected {:?} node at offset {}", kind, offset)
+            }
+            MapError::UnknownAttribute {
+                offset,
+                kind,
+                position,
+                attribute,
+            } => {
+                write!(
+                    fmt,
+                    "unknown attribute {} in {:?} node at offset {}",
+                    attribute, kind, offset
+                )?;
+
+                match position {
+                    Some(pos) => write!(fmt, " (tile {})", pos),
+                    None => Ok(()),
+                }
+            }
+            MapError::TileOutsideTileArea { offset, kind } => write!(
+                fmt,
+                "{:?} node outside of a TileArea at offset {}",
+                kind, offset
+            ),
+            MapError::TileOutsideMap {
+                offset,
+                kind,
+                origin,
+                area_offset,
+            } => write!(
+                fmt,
+                "{:?} node at offset {} is outside of the map ({:?} from TileArea {})",
+                kind, offset, area_offset, origin
+            ),
+            MapError::ItemOutsideTile { offset } => {
+                write!(fmt, "Item node outside of a Tile at offset {}", offset)
+            }
+            MapError::UnsupportedVersion { version } => {
+                write!(fmt, "unsupported OTBM version {}", version)
+            }
+            MapError::ItemTypesRequired { version } => {
+                write!(fmt, "item types are required for OTBM version {}", version)
+            }
+            MapError::UnknownCustomAttributeType { offset, position } => {
+                write!(
+                    fmt,
+                    "unknown custom attribute type in Item node at offset {}",
+                    offset
+                )?;
+
+                match position {
+                    Some(pos) => write!(fmt, " (tile {})", pos),
+                    None => Ok(()),
+                }
+            }
+        }
+    }
+}
+
+impl error::Error for MapError {}
+
+impl From<MapError> for io::Error {
+    fn from(err: MapError) -> io::Error {
+        io::Error::new(io::ErrorKind::InvalidData, err)
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct LoadOptions {
+    /// Skip unknown nodes and accept unknown attributes, collecting them in
+    /// `Loader::warnings` instead of failing. Unknown attributes are kept as
+    /// `RawAttribute`s, as is the rest of an Item node after an unknown
+    /// custom attribute type.
+    pub lenient: bool,
+    /// Only load tiles inside this region.
+    pub region: Option<Region>,
+    /// Only load tiles on these floors.
+    pub floors: Option<Vec<u8>>,
+}
+
+impl LoadOptions {
+    fn includes_floor(&self, z: u8) -> bool {
+        match &self.floors {
+            Some(floors) => floors.contains(&z),
+            None => true,
+        }
+    }
+
+    fn includes_area(&self, origin: Position) -> bool {
+        let in_region = match self.region {
+            Some(region) => region.intersects_area(origin),
+            None => true,
+        };
+
+        in_region && self.includes_floor(origin.z)
+    }
+
+    fn includes_tile(&self, pos: Position) -> bool {
+        let in_region = match self.region {
+            Some(region) => region.contains(pos),
+            None => true,
+        };
+
+        in_region && self.includes_floor(pos.z)
+    }
+}
+
+/// Inclusive bounding box of tile coordinates, spanning all floors.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Region {
+    pub min_x: u16,
+    pub min_y: u16,
+    pub max_x: u16,
+    pub max_y: u16,
+}
+
+impl Region {
+    pub fn contains(&self, pos: Position) -> bool {
+        (self.min_x..=self.max_x).contains(&pos.x) && (self.min_y..=self.max_y).contains(&pos.y)
+    }
+
+    /// Whether any tile of the TileArea at `origin` lies inside the region.
+    fn intersects_area(&self, origin: Position) -> bool {
+        origin.x <= self.max_x
+            && origin.x.saturating_add(0xFF) >= self.min_x
+            && origin.y <= self.max_y
+            && origin.y.saturating_add(0xFF) >= self.min_y
+    }
+}
+
+/// The root node header and the MapData attributes of an OTBM file.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct MapHeader {
     pub version: u32,
     pub width: u16,
     pub height: u16,
-    pub items_version: (u32, u32),
+    // Major version and client version of the items.otb the map was made with
+    items_version: (u32, u32),
 
     pub description: Vec<String>,
     pub house_file: Vec<String>,
     pub spawn_file: Vec<String>,
+    pub unknown_attribute: Option<RawAttribute>,
+}
+
+impl MapHeader {
+    pub fn items_version(&self) -> (u32, u32) {
+        self.items_version
+    }
+
+    /// Reads the data of the Root node: the version, the size and the items
+    /// version.
+    pub fn read_root_data(&mut self, mut data: &[u8]) -> io::Result<()> {
+        self.version = data.read_u32()?;
+        self.width = data.read_u16()?;
+        self.height = data.read_u16()?;
+        self.items_version = (data.read_u32()?, data.read_u32()?);
+
+        Ok(())
+    }
+
+    /// Reads the attributes of the MapData node.
+    pub fn read_map_data(&mut self, mut data: &[u8]) -> io::Result<()> {
+        while !data.is_empty() {
+            use self::NodeAttributeKind::*;
+            let raw_attr = data.read_byte()?;
+
+            match NodeAttributeKind::from_u8(raw_attr) {
+                Some(MapDescription) => self.description.push(data.read_string()?),
+                Some(HouseFile) => self.house_file.push(data.read_string()?),
+                Some(SpawnFile) => self.spawn_file.push(data.read_string()?),
+                _ => self.unknown_attribute = Some(RawAttribute::read(raw_attr, &mut data)),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Changes the items version the map is saved for. The major version has
+    /// to match the loaded item types, and the client version can't be newer
+    /// than theirs.
+    pub fn set_items_version(
+        &mut self,
+        items_version: (u32, u32),
+        items: &itemtypes::Container,
+    ) -> Result<(), map::EditError> {
+        let (major, minor, _) = items.version;
+
+        if items_version.0 != major || items_version.1 > minor {
+            return Err(map::EditError::IncompatibleItemsVersion {
+                items_version,
+                loaded: (major, minor),
+            });
+        }
+
+        self.items_version = items_version;
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Loader {
+    pub header: MapHeader,
 
+    /// Towns, waypoints and spawns read by `load`, until moved into a map
+    /// with `move_into`
     pub towns: Vec<Town>,
     pub waypoints: Vec<Waypoint>,
+    pub spawns: Vec<Spawn>,
+
+    pub warnings: Vec<MapError>,
+    /// Number of TileArea nodes skipped because of the region or floor filter
+    pub skipped_areas: usize,
+
+    options: LoadOptions,
+    parser: EventParser,
+    // Decompresses the stream read by `open` and `load`
+    input: StreamDecoder,
+    // Item types whose count is stored without an attribute in version 0,
+    // along with the count to write if an item has none
+    implicit_subtypes: Option<HashMap<u16, u8>>,
 
     current_tile_origin: Option<Position>,
     current_tile: Option<Position>,
-    current_tile_items: Vec<Item>,
+    current_tile_data: Tile,
+    // Indices leading to the last item at each container nesting level
+    current_item_path: Vec<usize>,
 }
 
 impl Loader {
+    /// Highest root node version understood by the loader and the writer.
+    pub const MAX_VERSION: u32 = 4;
+
+    /// The first version that stores waypoints.
+    const WAYPOINTS_VERSION: u32 = 2;
+
+    /// Reads the header of a map. Maps compressed with gzip or xz are
+    /// decompressed while they are read, here and by `load` on the same
+    /// stream; `load_slice` and `load_parallel` need the decompressed data.
     pub fn open<R>(r: R) -> io::Result<Loader>
+    where
+        R: io::Read,
+    {
+        Loader::open_with_options(r, LoadOptions::default())
+    }
+
+    pub fn open_with_options<R>(r: R, options: LoadOptions) -> io::Result<Loader>
     where
         R: io::Read,
     {
         let mut loader = Loader {
+            options,
             ..Default::default()
         };
 
-        binaryfile::streaming_parser(r, false, |kind, data| {
-            loader.load_headers_callback(kind, data)
+        let mut input = StreamDecoder::default();
+        let mut r = input.reader(r);
+
+        // File identifier, either zeroes or "OTBM"
+        let _identifier = r.read_u32()?;
+
+        let mut parser = EventParser::new(4, Vec::new());
+
+        parser.parse(r, |position, _, event| match event {
+            NodeEvent::Enter { kind, data } => {
+                loader.load_headers_callback(position.offset, kind, data)
+            }
+            NodeEvent::Exit { .. } => Ok(ParseAction::Continue),
         })?;
 
+        loader.parser = parser;
+        loader.input = input;
+
         Ok(loader)
     }
 
+    /// Provides the item types needed to load and save version 0 maps, which
+    /// store the count of stackable, splash and fluid items right after the
+    /// item id instead of in a Count attribute.
+    pub fn set_item_types(&mut self, items: &itemtypes::Container) {
+        let implicit_subtypes = items
+            .items
+            .values()
+            .filter_map(|item| {
+                if item.is_stackable() {
+                    Some((item.server_id, 1))
+                } else if item.is_splash() || item.is_fluid_container() {
+                    Some((item.server_id, 0))
+                } else {
+                    None
+                }
+            })
+            .collect();
+
+        self.implicit_subtypes = Some(implicit_subtypes);
+    }
+
+    fn check_item_types(&self, version: u32) -> io::Result<()> {
+        if version == 0 && self.implicit_subtypes.is_none() {
+            Err(MapError::ItemTypesRequired { version }.into())
+        } else {
+            Ok(())
+        }
+    }
+
     pub fn load<F>(&mut self, r: &mut dyn io::Read, mut tile_callback: F) -> io::Result<()>
     where
-        F: FnMut(Position, &[Item]),
+        F: FnMut(Position, Tile),
     {
-        binaryfile::streaming_parser(r, true, |kind, data| {
-            self.load_callback(kind, data, &mut tile_callback)
-        })
-    }
+        self.check_item_types(self.header.version)?;
 
-    fn load_headers_callback(&mut self, kind: u8, mut data: &[u8]) -> io::Result<bool> {
-        let kind = NodeKind::from_u8(kind).expect("unknown map node kind");
+        let mut parser = mem::take(&mut self.parser);
+        let mut input = mem::take(&mut self.input);
 
-        match kind {
-            NodeKind::Root => {
-                self.version = data.read_u32()?;
-                self.width = data.read_u16()?;
-                self.height = data.read_u16()?;
-                self.items_version = (data.read_u32()?, data.read_u32()?);
+        let mut callback = |position, path: &[u8], event: NodeEvent| {
+            self.load_callback(position, path, event, &mut tile_callback)
+        };
 
-                Ok(true)
-            }
+        let result = {
+            let reader: Box<dyn io::Read + '_> = match input.take_uncompressed() {
+                // Nothing to decompress, the rest is read straight from `r`
+                Some(read_ahead) => Box::new(io::Cursor::new(read_ahead).chain(r)),
+                None => Box::new(input.reader(r)),
+            };
 
-            NodeKind::MapData => {
-                while !data.is_empty() {
-                    use self::NodeAttributeKind::*;
-                    let raw_attr = data.read_byte()?;
-                    let attribute =
-                        NodeAttributeKind::from_u8(raw_attr).expect("unknown attribute");
-
-                    match attribute {
-                        MapDescription => self.description.push(data.read_string()?),
-                        HouseFile => self.house_file.push(data.read_string()?),
-                        SpawnFile => self.spawn_file.push(data.read_string()?),
-                        _ => panic!("Unknown map attribute"),
-                    }
-                }
+            // The parser reads byte by byte; load reads up to the end of the
+            // stream, so nothing buffered here is lost
+            parser.parse(io::BufReader::new(reader), &mut callback)
+        };
 
-                Ok(false)
-            }
+        self.parser = parser;
+        self.input = input;
+        result?;
 
-            _ => unreachable!(),
-        }
+        self.check_root_ended()
     }
 
-    fn load_callback<F>(
-        &mut self,
-        kind: u8,
-        mut data: &[u8],
-        mut tile_callback: F,
-    ) -> io::Result<bool>
+    /// Same as `load`, but reads the nodes straight out of `data`, the
+    /// complete contents of the file this loader was opened from (e.g. a
+    /// memory-mapped file), without copying unescaped node data.
+    pub fn load_slice<F>(&mut self, data: &[u8], mut tile_callback: F) -> io::Result<()>
     where
-        F: FnMut(Position, &[Item]),
+        F: FnMut(Position, Tile),
     {
-        let kind = NodeKind::from_u8(kind).expect("unknown map node kind");
+        self.check_item_types(self.header.version)?;
 
-        match kind {
-            NodeKind::TileArea => {
-                let origin = data.read_position()?;
-                self.current_tile_origin = Some(origin);
-            }
+        let mut parser = mem::take(&mut self.parser);
 
-            NodeKind::Tile | NodeKind::HouseTile => {
-                let x_offset = data.read_byte()? as u16;
-                let y_offset = data.read_byte()? as u16;
+        let result = parser.parse_slice(data, |position, path, event| {
+            self.load_callback(position, path, event, &mut tile_callback)
+        });
 
-                if let Some(origin) = self.current_tile_origin {
-                    if let Some(old_pos) = self.current_tile {
-                        tile_callback(old_pos, &self.current_tile_items);
-                        self.current_tile_items.clear();
-                    }
+        self.parser = parser;
+        result?;
 
-                    self.current_tile = Some(Position {
-                        x: origin.x + x_offset,
-                        y: origin.y + y_offset,
-                        z: origin.z,
-                    });
-                } else {
-                    panic!("Encountered Tile outside of a TileArea");
-                }
+        self.check_root_ended()
+    }
 
-                if kind == NodeKind::HouseTile {
-                    let _house_id = data.read_u32()?;
-                }
+    /// Loads the whole map from `data`, the complete contents of the file
+    /// this loader was opened from. TileArea nodes are decoded on up to
+    /// `threads` worker threads and merged into a single map.
+    pub fn load_parallel(&mut self, data: &[u8], threads: usize) -> io::Result<map::Map> {
+        self.check_item_types(self.header.version)?;
 
-                while !data.is_empty() {
-                    use self::NodeAttributeKind::*;
-                    let raw_attr = data.read_byte()?;
-                    let attr = NodeAttributeKind::from_u8(raw_attr).expect("unknown attribute");
-
-                    match attr {
-                        TileFlags => {
-                            let _flags = data.read_u32()?;
-                        }
-
-                        Item => {
-                            let item_id = data.read_u16()?;
-                            self.current_tile_items.push(self::Item {
-                                id: item_id,
-                                attributes: Vec::new(),
-                            });
-                        }
-
-                        _ => panic!("unexpected tile attribute {:?}", attr),
-                    }
-                }
-            }
+        let path = &self.parser.path().to_vec()[..];
+        let index = self.build_index(data)?;
 
-            NodeKind::Item => {
-                if self.current_tile.is_none() {
-                    panic!("Encountered Item outside of a Tile");
-                }
+        let chunk_size = index.areas.len().div_ceil(threads.max(1));
 
-                let item_id = data.read_u16()?;
+        let chunks = index.areas.chunks(chunk_size.max(1));
 
-                let mut item = Item {
-                    id: item_id,
-                    attributes: Vec::new(),
-                };
+        let results: Vec<io::Result<(map::Map, Loader)>> = if chunks.len() <= 1 {
+            // Not worth a thread
+            chunks
+                .map(|chunk| self.worker().load_areas(data, chunk, path))
+                .collect()
+        } else {
+            thread::scope(|scope| {
+                let workers: Vec<_> = chunks
+                    .map(|chunk| {
+                        let worker = self.worker();
+                        scope.spawn(move || worker.load_areas(data, chunk, path))
+                    })
+                    .collect();
 
-                while !data.is_empty() {
-                    use self::NodeAttributeKind::*;
-
-                    let raw_attr = data.read_byte()?;
-                    let attribute_kind =
-                        NodeAttributeKind::from_u8(raw_attr).expect("unknown attribute");
-
-                    let attribute = match attribute_kind {
-                        ItemCount | RuneCharges => ItemAttribute::Count(data.read_byte()?),
-                        ItemCharges => ItemAttribute::Charges(data.read_u16()?),
-                        ItemText => ItemAttribute::Text(data.read_string()?),
-                        ItemActionId => ItemAttribute::ActionId(data.read_u16()?),
-                        ItemUniqueId => ItemAttribute::UniqueId(data.read_u16()?),
-                        ItemWrittenDate => ItemAttribute::WrittenDate(data.read_u32()?),
-                        ItemWrittenBy => ItemAttribute::WrittenBy(data.read_string()?),
-                        ItemDescription => ItemAttribute::Description(data.read_string()?),
-                        ItemDuration => ItemAttribute::Duration(data.read_i32()?),
-                        ItemDecayingState => ItemAttribute::DecayingState(data.read_byte()?),
-                        ItemName => ItemAttribute::Name(data.read_string()?),
-                        ItemArticle => ItemAttribute::Article(data.read_string()?),
-                        ItemPluralName => ItemAttribute::PluralName(data.read_string()?),
-                        ItemWeight => ItemAttribute::Weight(data.read_u32()?),
-                        ItemAttack => ItemAttribute::Attack(data.read_i32()?),
-                        ItemDefense => ItemAttribute::Defense(data.read_i32()?),
-                        ItemExtraDefense => ItemAttribute::ExtraDefense(data.read_i32()?),
-                        ItemArmor => ItemAttribute::Armor(data.read_i32()?),
-                        ItemHitChance => ItemAttribute::HitChance(data.read_byte()?),
-                        ItemShootRange => ItemAttribute::ShootRange(data.read_byte()?),
-                        Teleport => ItemAttribute::Teleport(data.read_position()?),
-                        HouseDoorId => ItemAttribute::HouseDoorId(data.read_byte()?),
-                        DepotId => ItemAttribute::DepotId(data.read_u16()?),
-
-                        _ => panic!("Unknown item attribute"),
-                    };
+                workers
+                    .into_iter()
+                    .map(|worker| worker.join().expect("map loader thread panicked"))
+                    .collect()
+            })
+        };
 
-                    item.attributes.push(attribute);
-                }
+        let mut map = map::Map::new();
 
-                self.current_tile_items.push(item);
-            }
+        for result in results {
+            let (worker_map, worker) = result?;
 
-            NodeKind::Town => {
-                self.towns.push(Town::deserialize(&mut data)?);
-            }
+            map.merge(worker_map);
+            self.warnings.extend(worker.warnings);
+            self.skipped_areas += worker.skipped_areas;
+        }
 
-            NodeKind::WayPoint => {
-                self.waypoints.push(Waypoint::deserialize(&mut data)?);
+        // Towns, waypoints and spawns are small enough to load here
+        for range in index.others {
+            self.load_range(data, range, path, |pos, tile| {
+                *map.get_or_create(&pos).get_tile(&pos) = tile
+            })?;
+        }
+
+        self.move_into(&mut map);
+
+        Ok(map)
+    }
+
+    /// Finds the TileArea nodes and the other children of MapData in `data`,
+    /// the complete contents of the file this loader was opened from, without
+    /// decoding any tiles.
+    pub fn build_index(&self, data: &[u8]) -> io::Result<AreaIndex> {
+        // The header parser stops at the first child of MapData
+        let first_child = self.parser.offset() as usize;
+        let (nodes, end) = binaryfile::sibling_node_ranges(data, first_child)?;
+        self.check_root_ends_at(data, end)?;
+
+        let mut index = AreaIndex {
+            file_len: data.len() as u64,
+            ..Default::default()
+        };
+
+        for (kind, range) in nodes {
+            if kind != NodeKind::TileArea as u8 {
+                index.others.push(range);
+                continue;
             }
 
-            _ => println!("ignoring node {:?}", kind),
+            let mut origin = None;
+            let mut parser = EventParser::new(range.start as u64, Vec::new());
+
+            parser.parse_slice(node_data(data, &range)?, |_, _, event| {
+                if let NodeEvent::Enter { mut data, .. } = event {
+                    origin = Some(data.read_position()?);
+                }
+
+                Ok(ParseAction::Stop)
+            })?;
+
+            let origin = origin
+                .ok_or_else(|| io::Error::new(io::ErrorKind::UnexpectedEof, "unterminated node"))?;
+
+            index.areas.push(IndexedArea { origin, range });
         }
 
-        Ok(true)
+        Ok(index)
     }
-}
 
-#[derive(Debug)]
-pub struct Town {
-    pub id: u32,
-    pub name: String,
-    pub temple_position: Position,
-}
+    /// Copies the header and moves the towns, waypoints and spawns read so
+    /// far into `map`.
+    pub fn move_into(&mut self, map: &mut map::Map) {
+        *map.header_mut() = self.header.clone();
+        map.extend_unchecked(
+            mem::take(&mut self.towns),
+            mem::take(&mut self.waypoints),
+            mem::take(&mut self.spawns),
+        );
+    }
 
-impl Town {
-    pub fn deserialize<R>(mut r: R) -> io::Result<Town>
-    where
-        R: io::Read,
-    {
-        Ok(Town {
-            id: r.read_u32()?,
-            name: r.read_string()?,
-            temple_position: r.read_position()?,
-        })
+    /// Creates a loader for decoding parts of the same file on another thread.
+    fn worker(&self) -> Loader {
+        Loader {
+            header: MapHeader {
+                version: self.header.version,
+                ..Default::default()
+            },
+            options: self.options.clone(),
+            implicit_subtypes: self.implicit_subtypes.clone(),
+            ..Default::default()
+        }
     }
-}
 
-#[derive(Debug)]
-pub struct Waypoint {
-    pub name: String,
-    pub position: Position,
-}
+    /// Loads the given TileArea nodes into a new map, returning it along with
+    /// this loader so its warnings can be collected.
+    fn load_areas(
+        mut self,
+        data: &[u8],
+        areas: &[IndexedArea],
+        path: &[u8],
+    ) -> io::Result<(map::Map, Loader)> {
+        let mut map = map::Map::new();
 
-impl Waypoint {
-    pub fn deserialize<R>(mut r: R) -> io::Result<Waypoint>
+        for area in areas {
+            self.load_range(data, area.range.clone(), path, |pos, tile| {
+                *map.get_or_create(&pos).get_tile(&pos) = tile
+            })?;
+        }
+
+        Ok((map, self))
+    }
+
+    fn load_range<F>(
+        &mut self,
+        data: &[u8],
+        range: Range<usize>,
+        path: &[u8],
+        mut tile_callback: F,
+    ) -> io::Result<()>
     where
-        R: io::Read,
+        F: FnMut(Position, Tile),
     {
-        Ok(Waypoint {
-            name: r.read_string()?,
-            position: r.read_position()?,
+        let mut parser = EventParser::new(range.start as u64, path.to_vec());
+
+        parser.parse_slice(node_data(data, &range)?, |position, path, event| {
+            self.load_callback(position, path, event, &mut tile_callback)
         })
     }
+
+    // The parser only makes sure the nodes opened while loading end; Root
+    // and MapData were opened by `open`
+    fn check_root_ended(&self) -> io::Result<()> {
+        match self.parser.depth() {
+            0 => Ok(()),
+            _ => Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "unterminated node",
+            )),
+        }
+    }
+
+    // Nodes found by an index are parsed on their own, so the nodes `open`
+    // left open have to end at `end`, right before the end of `data`
+    fn check_root_ends_at(&self, data: &[u8], end: usize) -> io::Result<()> {
+        let ends = &data[end..];
+        let depth = self.parser.depth();
+
+        if ends.len() < depth || ends[..depth].iter().any(|&b| b != binaryfile::Node::END) {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "unterminated node",
+            ));
+        }
+
+        if ends.len() > depth {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "data after the end of the root node",
+            ));
+        }
+
+        Ok(())
+    }
+
+    // Called when a tile node ends
+    fn finish_tile<F>(&mut self, tile_callback: &mut F)
+    where
+        F: FnMut(Position, Tile),
+    {
+        if let Some(pos) = self.current_tile.take() {
+            tile_callback(pos, mem::take(&mut self.current_tile_data));
+        }
+    }
+
+    /// Saves `map` to a file, compressed with gzip or xz if the file name
+    /// ends in `.gz` or `.xz`.
+    ///
+    /// The map is written to a temporary file next to `path` that then
+    /// replaces it, so a map opened from the same file stays intact while
+    /// it is memory-mapped, and a failed save leaves the old file as it was.
+    pub fn save_file<P>(&self, path: P, map: &map::Map) -> io::Result<()>
+    where
+        P: AsRef<Path>,
+    {
+        let path = path.as_ref();
+
+        helpers::replace_file(path, |file| {
+            let mut w = Encoder::new(io::BufWriter::new(file), Compression::from_path(path));
+
+            self.save(&mut w, map)?;
+            w.finish()?.flush()
+        })
+    }
+
+    /// Writes `map`, including its header, towns, waypoints and spawns, as an
+    /// OTBM file. The loader only provides the item types needed for
+    /// version 0.
+    pub fn save<W>(&self, mut w: W, map: &map::Map) -> io::Result<()>
+    where
+        W: io::Write,
+    {
+        let header = map.header();
+
+        if !map.is_fully_loaded() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "not all areas of the map are loaded",
+            ));
+        }
+
+        if header.version > Loader::MAX_VERSION {
+            return Err(MapError::UnsupportedVersion {
+                version: header.version,
+            }
+            .into());
+        }
+
+        if header.version < Loader::WAYPOINTS_VERSION && map.waypoints().next().is_some() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "waypoints are not supported by this OTBM version",
+            ));
+        }
+
+        self.check_item_types(header.version)?;
+
+        // Only version 0 stores counts without an attribute
+        let implicit_subtypes = match header.version {
+            0 => self.implicit_subtypes.as_ref(),
+            _ => None,
+        };
+
+        // File identifier
+        w.write_u32(0)?;
+
+        let mut writer = NodeWriter::new(w);
+        let mut data = Vec::new();
+
+        data.write_u32(header.version)?;
+        data.write_u16(header.width)?;
+        data.write_u16(header.height)?;
+        data.write_u32(header.items_version.0)?;
+        data.write_u32(header.items_version.1)?;
+        writer.begin(NodeKind::Root as u8)?;
+        writer.write_data(&data)?;
+
+        data.clear();
+
+        for description in &header.description {
+            data.write_byte(NodeAttributeKind::MapDescription as u8)?;
+            data.write_string(description)?;
+        }
+
+        for spawn_file in &header.spawn_file {
+            data.write_byte(NodeAttributeKind::SpawnFile as u8)?;
+            data.write_string(spawn_file)?;
+        }
+
+        for house_file in &header.house_file {
+            data.write_byte(NodeAttributeKind::HouseFile as u8)?;
+            data.write_string(house_file)?;
+        }
+
+        if let Some(raw) = &header.unknown_attribute {
+            raw.serialize(&mut data)?;
+        }
+
+        writer.begin(NodeKind::MapData as u8)?;
+        writer.write_data(&data)?;
+
+        let mut tiles: Vec<_> = map.tiles().collect();
+
+        // Group tiles by the 256x256 area they are stored in
+        tiles.sort_by_key(|(pos, _)| (pos.z, pos.x & 0xFF00, pos.y & 0xFF00, pos.x, pos.y));
+
+        let mut current_area = None;
+
+        for (pos, tile) in tiles {
+            let area = Position {
+                x: pos.x & 0xFF00,
+                y: pos.y & 0xFF00,
+                z: pos.z,
+            };
+
+            if current_area != Some(area) {
+                if current_area.is_some() {
+                    writer.end()?;
+                }
+
+                data.clear();
+                data.write_position(&area)?;
+                writer.begin(NodeKind::TileArea as u8)?;
+                writer.write_data(&data)?;
+
+                current_area = Some(area);
+            }
+
+            data.clear();
+            data.write_byte((pos.x - area.x) as u8)?;
+            data.write_byte((pos.y - area.y) as u8)?;
+
+            if let Some(house_id) = tile.house_id {
+                data.write_u32(house_id)?;
+            }
+
+            if !tile.flags.is_empty() {
+                data.write_byte(NodeAttributeKind::TileFlags as u8)?;
+                data.write_u32(tile.flags.0)?;
+            }
+
+            // Items without attributes (typically ground) can be stored inline
+            let mut items = &tile.items[..];
+
+            if let Some(item) = items.first() {
+                if item.attributes.is_empty() && item.children.is_empty() {
+                    data.write_byte(NodeAttributeKind::Item as u8)?;
+                    data.write_u16(item.id)?;
+                    items = &items[1..];
+                }
+            }
+
+            if let Some(raw) = tile.unknown_attribute.as_deref() {
+                raw.serialize(&mut data)?;
+            }
+
+            let kind = match tile.house_id {
+                Some(_) => NodeKind::HouseTile,
+                None => NodeKind::Tile,
+            };
+
+            writer.begin(kind as u8)?;
+            writer.write_data(&data)?;
+
+            for item in items {
+                write_item_node(&mut writer, item, implicit_subtypes)?;
+            }
+
+            writer.end()?;
+        }
+
+        if current_area.is_some() {
+            writer.end()?;
+        }
+
+        if map.spawns().next().is_some() {
+            writer.begin(NodeKind::Spawns as u8)?;
+
+            for spawn in map.spawns() {
+                data.clear();
+                spawn.serialize(&mut data)?;
+                writer.begin(NodeKind::SpawnArea as u8)?;
+                writer.write_data(&data)?;
+
+                for monster in &spawn.monsters {
+                    data.clear();
+                    monster.serialize(&mut data)?;
+                    writer.begin(NodeKind::Monster as u8)?;
+                    writer.write_data(&data)?;
+                    writer.end()?;
+                }
+
+                writer.end()?;
+            }
+
+            writer.end()?;
+        }
+
+        writer.begin(NodeKind::Towns as u8)?;
+
+        for town in map.towns() {
+            data.clear();
+            town.serialize(&mut data)?;
+            writer.begin(NodeKind::Town as u8)?;
+            writer.write_data(&data)?;
+            writer.end()?;
+        }
+
+        writer.end()?;
+
+        if map.waypoints().next().is_some() {
+            writer.begin(NodeKind::WayPoints as u8)?;
+
+            for waypoint in map.waypoints() {
+                data.clear();
+                waypoint.serialize(&mut data)?;
+                writer.begin(NodeKind::WayPoint as u8)?;
+                writer.write_data(&data)?;
+                writer.end()?;
+            }
+
+            writer.end()?;
+        }
+
+        // MapData and Root
+        writer.end()?;
+        writer.end()
+    }
+
+    fn report(&mut self, error: MapError) -> io::Result<()> {
+        if self.options.lenient {
+            self.warnings.push(error);
+            Ok(())
+        } else {
+            Err(error.into())
+        }
+    }
+
+    fn load_headers_callback(
+        &mut self,
+        offset: u64,
+        raw_kind: u8,
+        data: &[u8],
+    ) -> io::Result<ParseAction> {
+        let kind = match NodeKind::from_u8(raw_kind) {
+            Some(kind) => kind,
+            None => {
+                return Err(MapError::UnknownNode {
+                    offset,
+                    kind: raw_kind,
+                }
+                .into())
+            }
+        };
+
+        match kind {
+            NodeKind::Root => {
+                self.header.read_root_data(data)?;
+
+                if self.header.version > Loader::MAX_VERSION {
+                    return Err(MapError::UnsupportedVersion {
+                        version: self.header.version,
+                    }
+                    .into());
+                }
+
+                Ok(ParseAction::Continue)
+            }
+
+            NodeKind::MapData => {
+                self.header.read_map_data(data)?;
+
+                if let Some(raw) = &self.header.unknown_attribute {
+                    self.report(MapError::UnknownAttribute {
+                        offset,
+                        kind,
+                        position: None,
+                        attribute: raw.kind,
+                    })?;
+                }
+
+                Ok(ParseAction::Stop)
+            }
+
+            _ => Err(MapError::UnexpectedNode { offset, kind }.into()),
+        }
+    }
+
+    fn load_callback<F>(
+        &mut self,
+        position: NodePosition,
+        path: &[u8],
+        event: NodeEvent,
+        tile_callback: &mut F,
+    ) -> io::Result<ParseAction>
+    where
+        F: FnMut(Position, Tile),
+    {
+        match event {
+            NodeEvent::Enter { kind, data } => self.enter_node(position.offset, path, kind, data),
+            NodeEvent::Exit { kind } => {
+                self.exit_node(path, kind, tile_callback);
+                Ok(ParseAction::Continue)
+            }
+        }
+    }
+
+    fn enter_node(
+        &mut self,
+        offset: u64,
+        path: &[u8],
+        raw_kind: u8,
+        mut data: &[u8],
+    ) -> io::Result<ParseAction> {
+        let kind = match NodeKind::from_u8(raw_kind) {
+            Some(kind) => kind,
+            None => {
+                self.report(MapError::UnknownNode {
+                    offset,
+                    kind: raw_kind,
+                })?;
+
+                return Ok(ParseAction::SkipChildren);
+            }
+        };
+
+        let parent = parent_kind(path);
+
+        match kind {
+            NodeKind::TileArea => {
+                let origin = data.read_position()?;
+                self.current_tile_origin = Some(origin);
+
+                if !self.options.includes_area(origin) {
+                    self.skipped_areas += 1;
+                    return Ok(ParseAction::SkipChildren);
+                }
+            }
+
+            NodeKind::Tile | NodeKind::HouseTile => {
+                let (area_offset, tile) = Tile::deserialize(kind, data)?;
+
+                let origin = match self.current_tile_origin {
+                    Some(origin) => origin,
+                    None => {
+                        self.report(MapError::TileOutsideTileArea { offset, kind })?;
+                        return Ok(ParseAction::SkipChildren);
+                    }
+                };
+
+                let pos = match Tile::position(origin, area_offset) {
+                    Some(pos) => pos,
+                    None => {
+                        self.report(MapError::TileOutsideMap {
+                            offset,
+                            kind,
+                            origin,
+                            area_offset,
+                        })?;
+                        return Ok(ParseAction::SkipChildren);
+                    }
+                };
+
+                if !self.options.includes_tile(pos) {
+                    return Ok(ParseAction::SkipChildren);
+                }
+
+                if let Some(raw) = &tile.unknown_attribute {
+                    self.report(MapError::UnknownAttribute {
+                        offset,
+                        kind,
+                        position: Some(pos),
+                        attribute: raw.kind,
+                    })?;
+                }
+
+                self.current_tile = Some(pos);
+                self.current_tile_data = tile;
+                self.current_item_path.clear();
+            }
+
+            NodeKind::Item => {
+                if !self.in_tile(parent) {
+                    self.report(MapError::ItemOutsideTile { offset })?;
+                    return Ok(ParseAction::SkipChildren);
+                }
+
+                // Only version 0 stores counts without an attribute
+                let implicit_subtypes = match self.header.version {
+                    0 => self.implicit_subtypes.as_ref(),
+                    _ => None,
+                };
+
+                let item = Item::deserialize(data, |id| {
+                    implicit_subtypes.is_some_and(|subtypes| subtypes.contains_key(&id))
+                })?;
+
+                for attribute in &item.attributes {
+                    if let ItemAttribute::Unknown(raw) = attribute {
+                        // Attribute maps are only unknown because of a value
+                        // type, the rest of the node is kept with them
+                        let error = if raw.kind == NodeAttributeKind::AttributeMap as u8 {
+                            MapError::UnknownCustomAttributeType {
+                                offset,
+                                position: self.current_tile,
+                            }
+                        } else {
+                            MapError::UnknownAttribute {
+                                offset,
+                                kind,
+                                position: self.current_tile,
+                                attribute: raw.kind,
+                            }
+                        };
+
+                        self.report(error)?;
+                    }
+                }
+
+                // Items nested in other items are container contents; the
+                // path leads to the container this item is added to
+                let items =
+                    container_items(&mut self.current_tile_data.items, &self.current_item_path)
+                        .expect("container path out of sync with the open items");
+
+                items.push(item);
+                self.current_item_path.push(items.len() - 1);
+            }
+
+            NodeKind::SpawnArea if parent == Some(NodeKind::Spawns) => {
+                self.spawns.push(Spawn::deserialize(&mut data)?);
+            }
+
+            NodeKind::Monster if parent == Some(NodeKind::SpawnArea) => {
+                // The SpawnArea was pushed when it was entered
+                if let Some(spawn) = self.spawns.last_mut() {
+                    spawn.monsters.push(Monster::deserialize(&mut data)?);
+                }
+            }
+
+            NodeKind::Town if parent == Some(NodeKind::Towns) => {
+                self.towns.push(Town::deserialize(&mut data)?);
+            }
+
+            NodeKind::WayPoint if parent == Some(NodeKind::WayPoints) => {
+                self.waypoints.push(Waypoint::deserialize(&mut data)?);
+            }
+
+            // Containers for the nodes above
+            NodeKind::Spawns | NodeKind::Towns | NodeKind::WayPoints => (),
+
+            // Misplaced nodes, and the ItemDef, TileSquare and TileRef nodes
+            // no known editor writes
+            _ => {
+                self.report(MapError::UnexpectedNode { offset, kind })?;
+                return Ok(ParseAction::SkipChildren);
+            }
+        }
+
+        Ok(ParseAction::Continue)
+    }
+
+    fn exit_node<F>(&mut self, path: &[u8], raw_kind: u8, tile_callback: &mut F)
+    where
+        F: FnMut(Position, Tile),
+    {
+        match NodeKind::from_u8(raw_kind) {
+            Some(NodeKind::TileArea) => self.current_tile_origin = None,
+            Some(NodeKind::Tile) | Some(NodeKind::HouseTile) => self.finish_tile(tile_callback),
+
+            // Items that were skipped when entered were never added
+            Some(NodeKind::Item) if self.in_tile(parent_kind(path)) => {
+                self.current_item_path.pop();
+            }
+
+            _ => (),
+        }
+    }
+
+    // Whether an item node with the given parent belongs to the current tile
+    fn in_tile(&self, parent: Option<NodeKind>) -> bool {
+        let parent_is_tile = matches!(
+            parent,
+            Some(NodeKind::Tile) | Some(NodeKind::HouseTile) | Some(NodeKind::Item)
+        );
+
+        self.current_tile.is_some() && parent_is_tile
+    }
+}
+
+/// The part of `data` the parser needs to read the node at `range`, which
+/// may come from an index that doesn't match the file.
+fn node_data<'a>(data: &'a [u8], range: &Range<usize>) -> io::Result<&'a [u8]> {
+    if range.start > range.end || range.end > data.len() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "node range outside of the file",
+        ));
+    }
+
+    Ok(&data[..range.end])
+}
+
+fn parent_kind(path: &[u8]) -> Option<NodeKind> {
+    path.last().and_then(|&kind| NodeKind::from_u8(kind))
+}
+
+/// Byte range of a TileArea node within an OTBM file, and its origin.
+#[derive(Clone, Debug, PartialEq)]
+pub struct IndexedArea {
+    pub origin: Position,
+    pub range: Range<usize>,
+}
+
+impl IndexedArea {
+    // Tiles are stored as offsets of up to 255 from the origin
+    const SIZE: u32 = 256;
+
+    /// Whether the area can have tiles in the square of `size` tiles at `pos`.
+    pub fn overlaps(&self, pos: Position, size: u16) -> bool {
+        let overlaps = |origin: u16, start: u16| {
+            let (origin, start) = (origin as u32, start as u32);
+            origin < start + size as u32 && start < origin + IndexedArea::SIZE
+        };
+
+        self.origin.z == pos.z && overlaps(self.origin.x, pos.x) && overlaps(self.origin.y, pos.y)
+    }
+}
+
+/// Locations of the children of MapData in an OTBM file, so TileAreas can
+/// be loaded as they are needed. Building it takes a scan over the whole
+/// file, so it can be saved next to the map for the next time.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct AreaIndex {
+    pub areas: Vec<IndexedArea>,
+    /// Spawns, towns and waypoints
+    pub others: Vec<Range<usize>>,
+
+    /// Length and modification time (in nanoseconds since the epoch) of the
+    /// file the index was built for, to tell when it is out of date
+    pub file_len: u64,
+    pub modified: u64,
+}
+
+impl AreaIndex {
+    const IDENTIFIER: u32 = u32::from_le_bytes(*b"OTBI");
+    const VERSION: u32 = 2;
+
+    /// Whether the index was built for a file of `file_len` bytes, and all
+    /// its nodes lie within it.
+    pub fn fits(&self, file_len: u64) -> bool {
+        let fits = |range: &Range<usize>| range.start <= range.end && range.end as u64 <= file_len;
+
+        self.file_len == file_len
+            && self.areas.iter().all(|area| fits(&area.range))
+            && self.others.iter().all(fits)
+    }
+
+    pub fn deserialize<R>(mut r: R) -> io::Result<AreaIndex>
+    where
+        R: io::Read,
+    {
+        if r.read_u32()? != AreaIndex::IDENTIFIER || r.read_u32()? != AreaIndex::VERSION {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "not an area index of a supported version",
+            ));
+        }
+
+        let mut index = AreaIndex {
+            file_len: r.read_u64()?,
+            modified: r.read_u64()?,
+            ..Default::default()
+        };
+
+        for _ in 0..r.read_u32()? {
+            let origin = r.read_position()?;
+            let range = read_range(&mut r)?;
+
+            index.areas.push(IndexedArea { origin, range });
+        }
+
+        for _ in 0..r.read_u32()? {
+            index.others.push(read_range(&mut r)?);
+        }
+
+        Ok(index)
+    }
+
+    pub fn serialize<W>(&self, mut w: W) -> io::Result<()>
+    where
+        W: io::Write,
+    {
+        w.write_u32(AreaIndex::IDENTIFIER)?;
+        w.write_u32(AreaIndex::VERSION)?;
+        w.write_u64(self.file_len)?;
+        w.write_u64(self.modified)?;
+
+        w.write_u32(self.areas.len() as u32)?;
+
+        for area in &self.areas {
+            w.write_position(&area.origin)?;
+            write_range(&mut w, &area.range)?;
+        }
+
+        w.write_u32(self.others.len() as u32)?;
+
+        for range in &self.others {
+            write_range(&mut w, range)?;
+        }
+
+        Ok(())
+    }
+}
+
+fn read_range<R>(mut r: R) -> io::Result<Range<usize>>
+where
+    R: io::Read,
+{
+    Ok(r.read_u64()? as usize..r.read_u64()? as usize)
+}
+
+fn write_range<W>(mut w: W, range: &Range<usize>) -> io::Result<()>
+where
+    W: io::Write,
+{
+    w.write_u64(range.start as u64)?;
+    w.write_u64(range.end as u64)
+}
+
+/// Loads the TileAreas of an OTBM file into a map as they are first needed,
+/// keeping the file in memory (usually mapped) until then. The file is
+/// released once every area is loaded.
+#[derive(Debug)]
+pub struct AreaPager {
+    data: Option<FileData>,
+    loader: Loader,
+    index: AreaIndex,
+    loaded: Vec<bool>,
+}
+
+impl AreaPager {
+    /// `loader` has to be opened from `data`, and `index` built for it.
+    pub fn new(data: FileData, loader: Loader, index: AreaIndex) -> AreaPager {
+        AreaPager {
+            data: Some(data),
+            loader,
+            loaded: vec![false; index.areas.len()],
+            index,
+        }
+    }
+
+    /// Opens the map at `path` with only its header, spawns, towns and
+    /// waypoints loaded, leaving the tiles to `map::Map::page_in`. The index
+    /// is read from next to the map if it is up to date, and written there
+    /// otherwise.
+    ///
+    /// Compressed maps are loaded completely: they have to be decompressed
+    /// into memory as a whole, so paging would not save any memory.
+    pub fn open<P>(
+        path: P,
+        options: LoadOptions,
+        items: &itemtypes::Container,
+    ) -> io::Result<map::Map>
+    where
+        P: AsRef<Path>,
+    {
+        let path = path.as_ref();
+        let data = compression::read_file(path)?;
+
+        let mut loader = Loader::open_with_options(&data[..], options)?;
+        loader.set_item_types(items);
+
+        let modified = fs::metadata(path)?
+            .modified()?
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |time| time.as_nanos() as u64);
+
+        let index_path = AreaPager::index_path(path);
+        let saved_index = File::open(&index_path)
+            .and_then(|file| AreaIndex::deserialize(io::BufReader::new(file)));
+
+        let index = match saved_index {
+            Ok(index) if index.modified == modified && index.fits(data.len() as u64) => index,
+            _ => {
+                let mut index = loader.build_index(&data)?;
+                index.modified = modified;
+
+                // The index only saves time, so it is fine if it can't be
+                // written, e.g. next to a map in a read-only directory
+                let _ = File::create(&index_path).and_then(|file| {
+                    let mut w = io::BufWriter::new(file);
+                    index.serialize(&mut w)?;
+                    w.flush()
+                });
+
+                index
+            }
+        };
+
+        let compressed = matches!(data, FileData::Decompressed(_));
+        let mut map = AreaPager::new(data, loader, index).into_map()?;
+
+        if compressed {
+            map.page_in_all()?;
+        }
+
+        Ok(map)
+    }
+
+    /// Where `open` keeps the index of the map at `path`.
+    pub fn index_path(path: &Path) -> PathBuf {
+        let mut index_path = path.as_os_str().to_owned();
+        index_path.push(".idx");
+        index_path.into()
+    }
+
+    /// Creates a map out of everything but the TileAreas, which are loaded
+    /// into it by `map::Map::page_in`.
+    pub fn into_map(mut self) -> io::Result<map::Map> {
+        let mut map = map::Map::new();
+        let path = self.loader.parser.path().to_vec();
+
+        if let Some(data) = &self.data {
+            for range in self.index.others.clone() {
+                self.loader.load_range(data, range, &path, |pos, tile| {
+                    *map.get_or_create(&pos).get_tile(&pos) = tile
+                })?;
+            }
+        }
+
+        self.loader.move_into(&mut map);
+        self.release_if_complete();
+        map.set_pager(self);
+
+        Ok(map)
+    }
+
+    /// The loader the areas are loaded with, which collects their warnings.
+    pub fn loader(&self) -> &Loader {
+        &self.loader
+    }
+
+    /// Loads the areas not loaded yet that can have tiles in the square of
+    /// `size` tiles at `pos` into `map`.
+    pub fn load(&mut self, map: &mut map::Map, pos: Position, size: u16) -> io::Result<()> {
+        self.load_areas_where(map, |area| area.overlaps(pos, size))
+    }
+
+    pub fn load_all(&mut self, map: &mut map::Map) -> io::Result<()> {
+        self.load_areas_where(map, |_| true)
+    }
+
+    /// Whether every area has been loaded.
+    pub fn is_complete(&self) -> bool {
+        self.loaded.iter().all(|&loaded| loaded)
+    }
+
+    fn load_areas_where<F>(&mut self, map: &mut map::Map, predicate: F) -> io::Result<()>
+    where
+        F: Fn(&IndexedArea) -> bool,
+    {
+        let data = match &self.data {
+            Some(data) => data,
+            None => return Ok(()),
+        };
+
+        let path = self.loader.parser.path().to_vec();
+        let mut result = Ok(());
+
+        for (area, loaded) in self.index.areas.iter().zip(&mut self.loaded) {
+            if *loaded || !predicate(area) {
+                continue;
+            }
+
+            // Broken areas are only reported once
+            *loaded = true;
+
+            result = self
+                .loader
+                .load_range(data, area.range.clone(), &path, |pos, tile| {
+                    // Tiles edited before their area was paged in are kept
+                    let existing = map.get_or_create(&pos).get_tile(&pos);
+
+                    if existing.is_empty() {
+                        *existing = tile;
+                    }
+                });
+
+            if result.is_err() {
+                break;
+            }
+        }
+
+        self.release_if_complete();
+        result
+    }
+
+    // Unmaps the file once nothing is left to load from it, so it can be
+    // replaced without the pager noticing
+    fn release_if_complete(&mut self) {
+        if self.is_complete() {
+            self.data = None;
+        }
+    }
+}
+
+fn write_item_node<W>(
+    writer: &mut NodeWriter<W>,
+    item: &Item,
+    implicit_subtypes: Option<&HashMap<u16, u8>>,
+) -> io::Result<()>
+where
+    W: io::Write,
+{
+    let mut data = Vec::new();
+
+    match implicit_subtypes.and_then(|subtypes| subtypes.get(&item.id)) {
+        Some(&default_count) => {
+            let is_count =
+                |attribute: &&ItemAttribute| matches!(attribute, ItemAttribute::Count(_));
+
+            let count = match item.attributes.iter().find(is_count) {
+                Some(ItemAttribute::Count(count)) => *count,
+                _ => default_count,
+            };
+
+            data.write_u16(item.id)?;
+            data.write_byte(count)?;
+
+            for attribute in item.ordered_attributes().filter(|a| !is_count(a)) {
+                attribute.serialize(&mut data)?;
+            }
+        }
+
+        None => item.serialize(&mut data)?,
+    }
+
+    writer.begin(NodeKind::Item as u8)?;
+    writer.write_data(&data)?;
+
+    for child in &item.children {
+        write_item_node(writer, child, implicit_subtypes)?;
+    }
+
+    writer.end()
+}
+
+fn container_items<'a>(items: &'a mut Vec<Item>, path: &[usize]) -> Option<&'a mut Vec<Item>> {
+    match path.split_first() {
+        Some((&index, rest)) => items
+            .get_mut(index)
+            .and_then(|item| container_items(&mut item.children, rest)),
+        None => Some(items),
+    }
+}
+
+impl Item {
+    /// Reads the data of an Item node. `has_count` tells whether an item id is
+    /// followed by a count without an attribute, as stackable, splash and
+    /// fluid items are in version 0. Container contents are not included.
+    pub fn deserialize<F>(mut data: &[u8], has_count: F) -> io::Result<Item>
+    where
+        F: FnOnce(u16) -> bool,
+    {
+        let mut item = Item {
+            id: data.read_u16()?,
+            ..Default::default()
+        };
+
+        if has_count(item.id) {
+            item.attributes
+                .push(ItemAttribute::Count(data.read_byte()?));
+        }
+
+        while !data.is_empty() {
+            let raw_attr = data.read_byte()?;
+            // Attributes that can't be read are kept from their start on
+            let mut start = data;
+
+            let attribute = match NodeAttributeKind::from_u8(raw_attr) {
+                Some(attribute_kind) => ItemAttribute::deserialize(attribute_kind, &mut data)?,
+                None => None,
+            };
+
+            let attribute = match attribute {
+                Some(attribute) => attribute,
+                None => {
+                    data = &[];
+                    ItemAttribute::Unknown(RawAttribute::read(raw_attr, &mut start))
+                }
+            };
+
+            item.attributes.push(attribute);
+        }
+
+        Ok(item)
+    }
+
+    /// Writes the node data of the item. Container contents are not included
+    /// since they are stored as child nodes.
+    pub fn serialize<W>(&self, mut w: W) -> io::Result<()>
+    where
+        W: io::Write,
+    {
+        w.write_u16(self.id)?;
+
+        for attribute in self.ordered_attributes() {
+            attribute.serialize(&mut w)?;
+        }
+
+        Ok(())
+    }
+
+    /// Attributes in the order they are written: unknown attributes extend
+    /// to the end of the node when loaded, so they have to come last.
+    fn ordered_attributes(&self) -> impl Iterator<Item = &ItemAttribute> {
+        let is_unknown =
+            |attribute: &&ItemAttribute| matches!(attribute, ItemAttribute::Unknown(_));
+
+        let known = self.attributes.iter().filter(move |a| !is_unknown(a));
+        let unknown = self.attributes.iter().filter(is_unknown);
+
+        known.chain(unknown)
+    }
+}
+
+impl ItemAttribute {
+    fn deserialize<R>(kind: NodeAttributeKind, mut r: R) -> io::Result<Option<ItemAttribute>>
+    where
+        R: io::Read,
+    {
+        use self::NodeAttributeKind::*;
+
+        let attribute = match kind {
+            ItemCount | RuneCharges => ItemAttribute::Count(r.read_byte()?),
+            ItemCharges => ItemAttribute::Charges(r.read_u16()?),
+            ItemText => ItemAttribute::Text(r.read_string()?),
+            ItemActionId => ItemAttribute::ActionId(r.read_u16()?),
+            ItemUniqueId => ItemAttribute::UniqueId(r.read_u16()?),
+            ItemWrittenDate => ItemAttribute::WrittenDate(r.read_u32()?),
+            ItemWrittenBy => ItemAttribute::WrittenBy(r.read_string()?),
+            ItemDescription => ItemAttribute::Description(r.read_string()?),
+            ItemDuration => ItemAttribute::Duration(r.read_i32()?),
+            ItemDecayingState => ItemAttribute::DecayingState(r.read_byte()?),
+            ItemName => ItemAttribute::Name(r.read_string()?),
+            ItemArticle => ItemAttribute::Article(r.read_string()?),
+            ItemPluralName => ItemAttribute::PluralName(r.read_string()?),
+            ItemWeight => ItemAttribute::Weight(r.read_u32()?),
+            ItemAttack => ItemAttribute::Attack(r.read_i32()?),
+            ItemDefense => ItemAttribute::Defense(r.read_i32()?),
+            ItemExtraDefense => ItemAttribute::ExtraDefense(r.read_i32()?),
+            ItemArmor => ItemAttribute::Armor(r.read_i32()?),
+            ItemHitChance => ItemAttribute::HitChance(r.read_byte()?),
+            ItemShootRange => ItemAttribute::ShootRange(r.read_byte()?),
+            Teleport => ItemAttribute::Teleport(r.read_position()?),
+            HouseDoorId => ItemAttribute::HouseDoorId(r.read_byte()?),
+            DepotId => ItemAttribute::DepotId(r.read_u16()?),
+            SleeperGuid => ItemAttribute::SleeperGuid(r.read_u32()?),
+            SleepStart => ItemAttribute::SleepStart(r.read_u32()?),
+
+            AttributeMap => {
+                let count = r.read_u16()?;
+                let mut map = BTreeMap::new();
+
+                for _ in 0..count {
+                    let key = r.read_string()?;
+
+                    match CustomAttribute::deserialize(&mut r)? {
+                        Some(value) => map.insert(key, value),
+                        None => return Ok(None),
+                    };
+                }
+
+                ItemAttribute::Custom(map)
+            }
+
+            _ => return Ok(None),
+        };
+
+        Ok(Some(attribute))
+    }
+
+    pub fn serialize<W>(&self, mut w: W) -> io::Result<()>
+    where
+        W: io::Write,
+    {
+        use self::NodeAttributeKind as Kind;
+
+        match self {
+            ItemAttribute::Count(v) => {
+                w.write_byte(Kind::ItemCount as u8)?;
+                w.write_byte(*v)
+            }
+            ItemAttribute::ActionId(v) => {
+                w.write_byte(Kind::ItemActionId as u8)?;
+                w.write_u16(*v)
+            }
+            ItemAttribute::UniqueId(v) => {
+                w.write_byte(Kind::ItemUniqueId as u8)?;
+                w.write_u16(*v)
+            }
+            ItemAttribute::Text(v) => {
+                w.write_byte(Kind::ItemText as u8)?;
+                w.write_string(v)
+            }
+            ItemAttribute::Description(v) => {
+                w.write_byte(Kind::ItemDescription as u8)?;
+                w.write_string(v)
+            }
+            ItemAttribute::Teleport(v) => {
+                w.write_byte(Kind::Teleport as u8)?;
+                w.write_position(v)
+            }
+            ItemAttribute::DepotId(v) => {
+                w.write_byte(Kind::DepotId as u8)?;
+                w.write_u16(*v)
+            }
+            ItemAttribute::HouseDoorId(v) => {
+                w.write_byte(Kind::HouseDoorId as u8)?;
+                w.write_byte(*v)
+            }
+            ItemAttribute::Duration(v) => {
+                w.write_byte(Kind::ItemDuration as u8)?;
+                w.write_i32(*v)
+            }
+            ItemAttribute::DecayingState(v) => {
+                w.write_byte(Kind::ItemDecayingState as u8)?;
+                w.write_byte(*v)
+            }
+            ItemAttribute::WrittenDate(v) => {
+                w.write_byte(Kind::ItemWrittenDate as u8)?;
+                w.write_u32(*v)
+            }
+            ItemAttribute::WrittenBy(v) => {
+                w.write_byte(Kind::ItemWrittenBy as u8)?;
+                w.write_string(v)
+            }
+            ItemAttribute::SleeperGuid(v) => {
+                w.write_byte(Kind::SleeperGuid as u8)?;
+                w.write_u32(*v)
+            }
+            ItemAttribute::SleepStart(v) => {
+                w.write_byte(Kind::SleepStart as u8)?;
+                w.write_u32(*v)
+            }
+            ItemAttribute::Charges(v) => {
+                w.write_byte(Kind::ItemCharges as u8)?;
+                w.write_u16(*v)
+            }
+            ItemAttribute::Name(v) => {
+                w.write_byte(Kind::ItemName as u8)?;
+                w.write_string(v)
+            }
+            ItemAttribute::Article(v) => {
+                w.write_byte(Kind::ItemArticle as u8)?;
+                w.write_string(v)
+            }
+            ItemAttribute::PluralName(v) => {
+                w.write_byte(Kind::ItemPluralName as u8)?;
+                w.write_string(v)
+            }
+            ItemAttribute::Weight(v) => {
+                w.write_byte(Kind::ItemWeight as u8)?;
+                w.write_u32(*v)
+            }
+            ItemAttribute::Attack(v) => {
+                w.write_byte(Kind::ItemAttack as u8)?;
+                w.write_i32(*v)
+            }
+            ItemAttribute::Defense(v) => {
+                w.write_byte(Kind::ItemDefense as u8)?;
+                w.write_i32(*v)
+            }
+            ItemAttribute::ExtraDefense(v) => {
+                w.write_byte(Kind::ItemExtraDefense as u8)?;
+                w.write_i32(*v)
+            }
+            ItemAttribute::Armor(v) => {
+                w.write_byte(Kind::ItemArmor as u8)?;
+                w.write_i32(*v)
+            }
+            ItemAttribute::HitChance(v) => {
+                w.write_byte(Kind::ItemHitChance as u8)?;
+                w.write_byte(*v)
+            }
+            ItemAttribute::ShootRange(v) => {
+                w.write_byte(Kind::ItemShootRange as u8)?;
+                w.write_byte(*v)
+            }
+            ItemAttribute::Custom(map) => {
+                if map.len() > u16::MAX as usize {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        "too many custom attributes",
+                    ));
+                }
+
+                w.write_byte(Kind::AttributeMap as u8)?;
+                w.write_u16(map.len() as u16)?;
+
+                for (key, value) in map {
+                    w.write_string(key)?;
+                    value.serialize(&mut w)?;
+                }
+
+                Ok(())
+            }
+            ItemAttribute::Unknown(raw) => raw.serialize(w),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Town {
+    pub id: u32,
+    pub name: String,
+    pub temple_position: Position,
+}
+
+impl Town {
+    pub fn deserialize<R>(mut r: R) -> io::Result<Town>
+    where
+        R: io::Read,
+    {
+        Ok(Town {
+            id: r.read_u32()?,
+            name: r.read_string()?,
+            temple_position: r.read_position()?,
+        })
+    }
+
+    pub fn serialize<W>(&self, mut w: W) -> io::Result<()>
+    where
+        W: io::Write,
+    {
+        w.write_u32(self.id)?;
+        w.write_string(&self.name)?;
+        w.write_position(&self.temple_position)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Waypoint {
+    pub name: String,
+    pub position: Position,
+}
+
+impl Waypoint {
+    pub fn deserialize<R>(mut r: R) -> io::Result<Waypoint>
+    where
+        R: io::Read,
+    {
+        Ok(Waypoint {
+            name: r.read_string()?,
+            position: r.read_position()?,
+        })
+    }
+
+    pub fn serialize<W>(&self, mut w: W) -> io::Result<()>
+    where
+        W: io::Write,
+    {
+        w.write_string(&self.name)?;
+        w.write_position(&self.position)
+    }
+}
+
+/// A spawn embedded in the map. Monsters follow their SpawnArea node and are
+/// positioned relative to its center.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Spawn {
+    pub center: Position,
+    pub radius: u32,
+    pub monsters: Vec<Monster>,
+}
+
+impl Spawn {
+    pub fn deserialize<R>(mut r: R) -> io::Result<Spawn>
+    where
+        R: io::Read,
+    {
+        Ok(Spawn {
+            center: r.read_position()?,
+            radius: r.read_u32()?,
+            monsters: Vec::new(),
+        })
+    }
+
+    pub fn serialize<W>(&self, mut w: W) -> io::Result<()>
+    where
+        W: io::Write,
+    {
+        w.write_position(&self.center)?;
+        w.write_u32(self.radius)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Monster {
+    pub name: String,
+    pub offset: (i16, i16),
+    /// Respawn time in seconds
+    pub spawn_time: u32,
+}
+
+impl Monster {
+    pub fn deserialize<R>(mut r: R) -> io::Result<Monster>
+    where
+        R: io::Read,
+    {
+        Ok(Monster {
+            name: r.read_string()?,
+            offset: (r.read_i16()?, r.read_i16()?),
+            spawn_time: r.read_u32()?,
+        })
+    }
+
+    pub fn serialize<W>(&self, mut w: W) -> io::Result<()>
+    where
+        W: io::Write,
+    {
+        w.write_string(&self.name)?;
+        w.write_i16(self.offset.0)?;
+        w.write_i16(self.offset.1)?;
+        w.write_u32(self.spawn_time)
+    }
+
+    pub fn position(&self, center: Position) -> Position {
+        Position {
+            x: (i32::from(center.x) + i32::from(self.offset.0)) as u16,
+            y: (i32::from(center.y) + i32::from(self.offset.1)) as u16,
+            z: center.z,
+        }
+    }
 }
@@ -1,12 +1,52 @@
-use crate::helpers::ReadExt;
+use crate::helpers::{ReadExt, WriteExt};
+use serde::Serialize;
 use std::fmt;
 use std::io;
 
+/// Declares a newtype over raw `u32` flags, with helpers to test and change
+/// them. Unknown bits are kept so they survive a save.
+macro_rules! raw_flags {
+    ($(#[$meta:meta])* $name:ident) => {
+        $(#[$meta])*
+        #[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Serialize)]
+        pub struct $name(pub u32);
+
+        impl $name {
+            pub fn is_empty(self) -> bool {
+                self.0 == 0
+            }
+
+            pub fn contains(self, flag: u32) -> bool {
+                self.0 & flag == flag
+            }
+
+            pub fn set(&mut self, flag: u32, value: bool) {
+                if value {
+                    self.0 |= flag;
+                } else {
+                    self.0 &= !flag;
+                }
+            }
+        }
+    };
+}
+
 pub mod binaryfile;
+pub mod dump;
+pub mod itemsxml;
 pub mod itemtypes;
 pub mod map;
 
-#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+/// An attribute a reader doesn't understand, such as a server specific
+/// extension or a deprecated attribute. It is kept as is so it can be
+/// written back.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct RawAttribute {
+    pub kind: u8,
+    pub data: Vec<u8>,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, Serialize)]
 pub struct Position {
     pub x: u16,
     pub y: u16,
@@ -24,6 +64,15 @@ impl Position {
             z: r.read_byte()?,
         })
     }
+
+    pub fn serialize<W>(&self, mut w: W) -> io::Result<()>
+    where
+        W: io::Write,
+    {
+        w.write_u16(self.x)?;
+        w.write_u16(self.y)?;
+        w.write_byte(self.z)
+    }
 }
 
 impl fmt::Display for Position {
@@ -24,7 +24,7 @@ fn get_sprite_id(
     x: u8,
     y: u8,
 ) -> usize {
-    let animation_time = 0 % 4095;
+    let animation_time = 0;
 
     let mut idx = animation_time;
     idx = idx * obj.pattern_height as u16 + pattern_y % obj.pattern_height as u16;
@@ -98,15 +98,27 @@ impl<V> Renderer<V> {
         for (pos, tile) in sector {
             let mut elevation = 0;
 
-            for item in tile {
-                let otb_entry = &self.otb.items[item.id as usize];
-
-                let client_id = match otb_entry.client_id {
+            for item in &tile.items {
+                // Items whose server id is not in the OTB (e.g. removed from
+                // it since the map was saved) are skipped
+                let client_id = match self
+                    .otb
+                    .items
+                    .get(item.id as usize)
+                    .and_then(|otb_entry| otb_entry.client_id)
+                {
                     Some(v) => v,
                     None => continue,
                 };
 
-                let obj = &self.dat.items[(client_id - 100) as usize];
+                // Client ids missing from the dat are reported by the asset check
+                let obj = match (client_id as usize)
+                    .checked_sub(100)
+                    .and_then(|index| self.dat.items.get(index))
+                {
+                    Some(obj) => obj,
+                    None => continue,
+                };
 
                 let pattern_x = pos.x % obj.pattern_width as u16;
                 let pattern_y = pos.y % obj.pattern_height as u16;
@@ -115,7 +127,7 @@ impl<V> Renderer<V> {
                     for y in 0..obj.height {
                         for x in 0..obj.width {
                             let spr_idx = get_sprite_id(obj, layer, pattern_x, pattern_y, x, y);
-                            let spr_id = obj.sprite_ids[spr_idx] as u32;
+                            let spr_id = obj.sprite_ids[spr_idx];
 
                             if spr_id == 0 {
                                 continue;
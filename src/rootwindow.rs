@@ -2,7 +2,8 @@ use std::time::Instant;
 
 use cgmath::{self, Zero};
 
-use std::{cmp, f32, fs, io};
+use std::borrow::Cow;
+use std::{cmp, f32, fs, io, mem};
 
 use glium::glutin;
 use glium::glutin::dpi::PhysicalPosition;
@@ -21,7 +22,34 @@ pub struct Vertex {
     pub tex_coord: [f32; 2],
 }
 
-implement_vertex!(Vertex, position, color, tex_coord);
+// What implement_vertex! generates, without the offset_of! of the memoffset
+// version glium depends on, which checks a cfg unknown to this crate
+impl glium::Vertex for Vertex {
+    fn build_bindings() -> glium::VertexFormat {
+        use glium::vertex::Attribute;
+
+        Cow::Owned(vec![
+            (
+                Cow::Borrowed("position"),
+                mem::offset_of!(Vertex, position),
+                <[f32; 3]>::get_type(),
+                false,
+            ),
+            (
+                Cow::Borrowed("color"),
+                mem::offset_of!(Vertex, color),
+                <[f32; 4]>::get_type(),
+                false,
+            ),
+            (
+                Cow::Borrowed("tex_coord"),
+                mem::offset_of!(Vertex, tex_coord),
+                <[f32; 2]>::get_type(),
+                false,
+            ),
+        ])
+    }
+}
 
 pub struct RootWindow {
     display: glium::backend::glutin::Display,
@@ -139,8 +167,10 @@ impl RootWindow {
 
         let mut sprite_callback = |(x, y), sprite_id| {
             let tex_pos = atlas.get_or_load(sprite_id, |buf, stride| {
-                spr.get_sprite(sprite_id, buf, stride)
-                    .expect("failed to load sprite")
+                // A broken sprite is left transparent instead of aborting
+                if let Err(err) = spr.get_sprite(sprite_id, buf, stride) {
+                    println!("warning: failed to load sprite {}: {}", sprite_id, err);
+                }
             });
 
             Vertex {
@@ -156,6 +186,11 @@ impl RootWindow {
         self.vertex_buffer.invalidate();
 
         for sector_pos in &vis {
+            // Maps opened lazily load the areas coming into view
+            if let Err(err) = self.renderer.map.page_in(sector_pos) {
+                println!("warning: failed to load sector {}: {}", sector_pos, err);
+            }
+
             let vertices = self
                 .renderer
                 .get_sector_vertices(*sector_pos, &mut sprite_callback);
@@ -207,7 +242,7 @@ impl RootWindow {
                         return;
                     },
 
-                    Resized(new_size) => self.resize(new_size.width as u32, new_size.height as u32),
+                    Resized(new_size) => self.resize(new_size.width, new_size.height),
 
                     CursorMoved { position, .. } => {
                         if self.dragging {
@@ -1,3 +1,4 @@
+use crate::clientversion::{Client, Profile};
 use crate::helpers::ReadExt;
 use std::io;
 
@@ -8,17 +9,31 @@ pub struct SpriteContainer<R> {
     pub offsets: Vec<u32>,
 }
 
-// TODO:
-//  Support for u16 num_sprites
-//  Autodetect u16/u32 based on file signature
-
 impl<R> SpriteContainer<R>
 where
     R: io::Read + io::Seek,
 {
+    /// Reads a spr file, with the sprite count size of the client its
+    /// signature belongs to, or that of the newest client if it is unknown.
     pub fn new(mut r: R) -> io::Result<SpriteContainer<R>> {
         let signature = r.read_u32()?;
-        let num_sprites = r.read_u32()?;
+        let profile = Client::from_spr_signature(signature)
+            .map_or_else(Profile::default, |client| client.profile);
+
+        SpriteContainer::read(r, signature, profile)
+    }
+
+    pub fn with_profile(mut r: R, profile: Profile) -> io::Result<SpriteContainer<R>> {
+        let signature = r.read_u32()?;
+
+        SpriteContainer::read(r, signature, profile)
+    }
+
+    fn read(mut r: R, signature: u32, profile: Profile) -> io::Result<SpriteContainer<R>> {
+        let num_sprites = match profile.extended {
+            true => r.read_u32()?,
+            false => r.read_u16()? as u32,
+        };
 
         let mut offsets = Vec::with_capacity(num_sprites as usize);
 
@@ -40,7 +55,17 @@ where
         output: &mut [u8],
         output_stride: usize,
     ) -> io::Result<()> {
-        let offset = self.offsets[idx as usize - 1];
+        let offset = match idx
+            .checked_sub(1)
+            .and_then(|i| self.offsets.get(i as usize))
+        {
+            Some(&offset) => offset,
+            None => {
+                let problem = format!("is out of range ({} sprites)", self.num_sprites);
+                return Err(invalid_sprite(idx, &problem));
+            }
+        };
+
         self.f.seek(io::SeekFrom::Start(offset as u64))?;
 
         // RGB color key (typically magenta)
@@ -48,7 +73,7 @@ where
         self.f.read_byte()?;
         self.f.read_byte()?;
 
-        let mut size = self.f.read_u16()?;
+        let mut size = self.f.read_u16()? as usize;
         let (mut p, mut i) = (0, 0);
 
         let bytes_to_next_row = output_stride - 32 * 4;
@@ -63,7 +88,12 @@ where
             p += transparent_pixels * 4 + bytes_to_next_row * rows_skipped;
 
             for _ in 0..pixels {
-                self.f.read_exact(&mut output[p..p + 3])?;
+                let pixel = match output.get_mut(p..p + 4) {
+                    Some(pixel) => &mut pixel[..3],
+                    None => return Err(invalid_sprite(idx, "doesn't fit into 32x32 pixels")),
+                };
+
+                self.f.read_exact(pixel)?;
 
                 // Set alpha channel
                 output[p + 3] = 255;
@@ -75,9 +105,18 @@ where
                 }
             }
 
-            size -= 2 + 2 + pixels * 3;
+            size = size
+                .checked_sub(2 + 2 + pixels as usize * 3)
+                .ok_or_else(|| invalid_sprite(idx, "is longer than its size"))?;
         }
 
         Ok(())
     }
 }
+
+fn invalid_sprite(idx: u32, problem: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("sprite {} {}", idx, problem),
+    )
+}
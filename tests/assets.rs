@@ -0,0 +1,73 @@
+mod common;
+
+use mapeditor::assets::{self, AssetIssue};
+use mapeditor::clientversion::Profile;
+use mapeditor::datcontainer::{DatContainer, Thing};
+
+use common::*;
+
+fn thing(sprite_ids: Vec<u32>) -> Thing {
+    Thing {
+        flags: vec![],
+        width: 1,
+        height: 1,
+        exact_size: None,
+        layers: 1,
+        pattern_width: 1,
+        pattern_height: 1,
+        pattern_depth: 1,
+        displacement: (0, 0),
+        elevation: 0,
+        animation_length: 1,
+        animation: None,
+        sprite_ids,
+        group_type: None,
+        groups: vec![],
+    }
+}
+
+#[test]
+fn asset_check_reports_mismatched_files() {
+    // Client ids 200, 3031, 2874 and 2886 are used by the item types
+    let mut otb = item_types();
+    otb.version = (3, 20, 0);
+
+    let mut items: Vec<_> = (100..=3000).map(|_| thing(vec![1])).collect();
+    items[200 - 100] = thing(vec![0, 7]);
+    items[2874 - 100] = thing(vec![6]);
+
+    let dat = DatContainer {
+        signature: 0x42A3,
+        profile: Profile::default(),
+        items,
+        creatures: Vec::new(),
+        effects: Vec::new(),
+        missiles: Vec::new(),
+    };
+
+    let issues = assets::check(&otb, &dat, 6);
+    let errors: Vec<_> = issues.iter().filter(|issue| issue.is_error()).collect();
+
+    assert_eq!(
+        vec![
+            &AssetIssue::SignatureMismatch {
+                client: "8.60",
+                expected: 0x4C2C_7993,
+                found: 0x42A3
+            },
+            &AssetIssue::MissingClientId {
+                server_id: STACKABLE_ID,
+                client_id: 3031
+            },
+            &AssetIssue::SpriteOutOfRange {
+                client_id: 200,
+                sprite_id: 7
+            },
+        ],
+        errors
+    );
+
+    assert!(issues.contains(&AssetIssue::UnreferencedClientId { client_id: 100 }));
+    assert!(!issues.contains(&AssetIssue::UnreferencedClientId { client_id: 2886 }));
+    assert_eq!(3000 - 100 + 1 - 3, issues.len() - errors.len());
+}
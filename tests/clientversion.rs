@@ -0,0 +1,275 @@
+use std::io::Cursor;
+
+use mapeditor::clientversion::{Client, Profile};
+use mapeditor::datcontainer::{Animation, Attribute, DatContainer, Flag, FlagData};
+use mapeditor::spritecontainer::SpriteContainer;
+
+fn dat_header(signature: u32, num_items: u16) -> Vec<u8> {
+    let mut data = signature.to_le_bytes().to_vec();
+    data.extend_from_slice(&num_items.to_le_bytes());
+    data.extend_from_slice(&[0; 6]);
+    data
+}
+
+#[test]
+fn flags_are_mapped_to_the_10x_numbering() {
+    let v740 = Client::named("7.40").unwrap().profile;
+    assert_eq!(Attribute::MultiUse as u8, v740.flag(5));
+    assert_eq!(Attribute::ForceUse as u8, v740.flag(6));
+    assert_eq!(Attribute::Light as u8, v740.flag(16));
+    assert_eq!(Attribute::FloorChange as u8, v740.flag(17));
+
+    let v854 = Client::from_otb_version(17).unwrap().profile;
+    assert_eq!(Attribute::Charges as u8, v854.flag(8));
+    assert_eq!(Attribute::Writable as u8, v854.flag(9));
+    assert_eq!(Attribute::Pickupable as u8, v854.flag(17));
+    assert_eq!(Attribute::End as u8, v854.flag(0xFF));
+
+    let v860 = Client::named("8.60").unwrap().profile;
+    assert_eq!(Attribute::NotPathable as u8, v860.flag(15));
+    assert_eq!(Attribute::Pickupable as u8, v860.flag(16));
+
+    assert_eq!(
+        Attribute::NoMoveAnimation as u8,
+        Profile::default().flag(16)
+    );
+}
+
+#[test]
+fn dat_files_are_read_with_the_profile_of_their_signature() {
+    let mut data = dat_header(0x4C2C_7993, 101);
+
+    // Ground with speed 150, pickupable, displaced by (4, 4)
+    data.extend_from_slice(&[0, 150, 0, 16, 24, 4, 0, 4, 0, 0xFF]);
+    // 1x1, one layer, 1x1x1 patterns, two animation frames without durations
+    data.extend_from_slice(&[1, 1, 1, 1, 1, 1, 2]);
+    data.extend_from_slice(&[5, 0, 6, 0]);
+
+    // 2x1 with its exact size byte
+    data.extend_from_slice(&[0xFF, 2, 1, 64, 1, 1, 1, 1, 1]);
+    data.extend_from_slice(&[7, 0, 8, 0]);
+
+    let dat = DatContainer::new(&mut &data[..]).unwrap();
+    assert_eq!(Client::named("8.60").unwrap().profile, dat.profile);
+    assert_eq!(2, dat.items.len());
+    assert_eq!((4, 4), dat.items[0].displacement);
+    assert_eq!(vec![5, 6], dat.items[0].sprite_ids);
+    assert_eq!(
+        vec![
+            Flag {
+                raw: 0,
+                attribute: Attribute::Ground,
+                data: FlagData::Value(150),
+            },
+            Flag {
+                raw: 16,
+                attribute: Attribute::Pickupable,
+                data: FlagData::None,
+            },
+            Flag {
+                raw: 24,
+                attribute: Attribute::Displacement,
+                data: FlagData::Displacement { x: 4, y: 4 },
+            },
+        ],
+        dat.items[0].flags
+    );
+    assert_eq!(None, dat.items[0].exact_size);
+
+    assert_eq!((2, 1), (dat.items[1].width, dat.items[1].height));
+    assert_eq!(Some(64), dat.items[1].exact_size);
+    assert_eq!(vec![7, 8], dat.items[1].sprite_ids);
+}
+
+#[test]
+fn old_dat_files_lack_the_pattern_depth() {
+    let mut data = dat_header(0x41BF_619C, 100);
+
+    // Displaced without offsets, with light
+    data.extend_from_slice(&[20, 16, 3, 0, 215, 0, 0xFF]);
+    // 1x1, one layer, 2x2 patterns and no depth, one frame
+    data.extend_from_slice(&[1, 1, 1, 2, 2, 1]);
+    data.extend_from_slice(&[1, 0, 2, 0, 3, 0, 4, 0]);
+
+    let dat = DatContainer::new(&mut &data[..]).unwrap();
+    let thing = &dat.items[0];
+    assert_eq!((8, 8), thing.displacement);
+    assert_eq!(
+        FlagData::Light {
+            intensity: 3,
+            color: 215,
+        },
+        thing.flags[1].data
+    );
+    assert_eq!(
+        (2, 2, 1),
+        (
+            thing.pattern_width,
+            thing.pattern_height,
+            thing.pattern_depth
+        )
+    );
+    assert_eq!(vec![1, 2, 3, 4], thing.sprite_ids);
+}
+
+#[test]
+fn old_spr_files_have_a_short_sprite_count() {
+    let mut data = 0x4C22_0594u32.to_le_bytes().to_vec();
+    data.extend_from_slice(&2u16.to_le_bytes());
+    data.extend_from_slice(&[10, 0, 0, 0, 20, 0, 0, 0]);
+
+    let spr = SpriteContainer::new(Cursor::new(&data)).unwrap();
+    assert_eq!(2, spr.num_sprites);
+    assert_eq!(vec![10, 20], spr.offsets);
+
+    let spr = SpriteContainer::with_profile(Cursor::new(&data), Profile::default());
+    assert!(spr.map_or(true, |spr| spr.num_sprites != 2));
+}
+
+fn frames(data: &mut Vec<u8>, patterns: (u8, u8), animation: u8, first_sprite: u32) {
+    data.extend_from_slice(&[1, 1, 1, patterns.0, patterns.1, 1, animation]);
+
+    if animation > 1 {
+        data.extend_from_slice(&[0, 0, 0, 0, 0, 0]);
+        data.extend((0..animation).flat_map(|_| [100, 0, 0, 0, 100, 0, 0, 0]));
+    }
+
+    let count = patterns.0 as u32 * patterns.1 as u32 * animation as u32;
+    data.extend((first_sprite..first_sprite + count).flat_map(u32::to_le_bytes));
+}
+
+#[test]
+fn dat_files_include_creatures_and_effects() {
+    let mut data = dat_header(0x42A3, 100);
+    data[6..12].copy_from_slice(&[1, 0, 1, 0, 1, 0]);
+
+    // Sold in the market as "axe", used by default
+    data.extend_from_slice(&[34, 1, 0, 100, 0, 100, 0, 3, 0, b'a', b'x', b'e', 0, 0, 8, 0]);
+    data.extend_from_slice(&[35, 2, 0, 0xFF]);
+    frames(&mut data, (1, 1), 1, 1);
+
+    // An outfit facing four directions, with idle and walking groups
+    data.extend_from_slice(&[0xFF, 2, 0]);
+    frames(&mut data, (4, 1), 1, 2);
+    data.push(1);
+    frames(&mut data, (4, 1), 2, 6);
+
+    data.push(0xFF);
+    frames(&mut data, (1, 1), 3, 14);
+
+    // A missile flying in eight directions
+    data.push(0xFF);
+    frames(&mut data, (3, 3), 1, 17);
+
+    let dat = DatContainer::new(&mut &data[..]).unwrap();
+    assert_eq!(vec![1], dat.items[0].sprite_ids);
+    assert_eq!(
+        vec![
+            FlagData::Market {
+                category: 1,
+                trade_as: 100,
+                show_as: 100,
+                name: "axe".into(),
+                vocation: 0,
+                level: 8,
+            },
+            FlagData::Value(2),
+        ],
+        dat.items[0]
+            .flags
+            .iter()
+            .map(|flag| flag.data.clone())
+            .collect::<Vec<_>>()
+    );
+
+    let outfit = &dat.creatures[0];
+    assert_eq!(vec![2, 3, 4, 5], outfit.sprite_ids);
+    assert_eq!(Some(0), outfit.group_type);
+    assert_eq!(1, outfit.groups.len());
+
+    let walking = outfit.walking().unwrap();
+    assert_eq!(2, walking.animation_length);
+    assert_eq!((6..14).collect::<Vec<_>>(), walking.sprite_ids);
+
+    assert_eq!(3, dat.effects[0].animation_length);
+    assert_eq!(
+        Some(Animation {
+            mode: 0,
+            loop_count: 0,
+            start_phase: 0,
+            durations: vec![(100, 100); 3],
+        }),
+        dat.effects[0].animation
+    );
+    assert_eq!(vec![14, 15, 16], dat.effects[0].sprite_ids);
+    assert_eq!((17..26).collect::<Vec<_>>(), dat.missiles[0].sprite_ids);
+}
+
+#[test]
+fn old_outfits_have_a_single_frame_group() {
+    let mut data = dat_header(0x4C2C_7993, 99);
+    data[6..8].copy_from_slice(&[1, 0]);
+    data.extend_from_slice(&[0xFF, 1, 1, 1, 1, 1, 1, 1, 9, 0]);
+
+    let dat = DatContainer::new(&mut &data[..]).unwrap();
+    assert!(dat.items.is_empty());
+    assert_eq!(vec![9], dat.creatures[0].sprite_ids);
+    assert!(dat.creatures[0].walking().is_none());
+}
+
+#[test]
+fn broken_sprites_are_errors() {
+    let mut data = 0x4C22_0594u32.to_le_bytes().to_vec();
+    data.extend_from_slice(&2u16.to_le_bytes());
+    data.extend_from_slice(&[14, 0, 0, 0, 26, 0, 0, 0]);
+
+    // One red pixel after 1023 transparent ones
+    data.extend_from_slice(&[0xFF, 0, 0xFF, 7, 0, 0xFF, 3, 1, 0, 0xFF, 0, 0]);
+    // 1024 transparent pixels, then one too many
+    data.extend_from_slice(&[0xFF, 0, 0xFF, 7, 0, 0, 4, 1, 0, 0xFF, 0, 0]);
+
+    let mut spr = SpriteContainer::new(Cursor::new(&data)).unwrap();
+    let mut output = vec![0; 32 * 32 * 4];
+
+    spr.get_sprite(1, &mut output, 32 * 4).unwrap();
+    assert_eq!([0xFF, 0, 0, 0xFF], output[1023 * 4..]);
+
+    for idx in [0, 2, 3] {
+        let err = spr.get_sprite(idx, &mut output, 32 * 4).unwrap_err();
+        assert_eq!(std::io::ErrorKind::InvalidData, err.kind());
+    }
+}
+
+#[test]
+fn unknown_dat_flags_are_errors() {
+    let mut data = dat_header(0x4C2C_7993, 100);
+    data.extend_from_slice(&[0xF0, 0xFF]);
+
+    let err = DatContainer::new(&mut &data[..]).map(|_| ()).unwrap_err();
+    assert_eq!(std::io::ErrorKind::InvalidData, err.kind());
+    assert!(err.to_string().contains("240"));
+    assert!(err.to_string().contains("V860"));
+}
+
+#[test]
+fn profiles_can_be_configured_for_unknown_clients() {
+    // E.g. a 9.x client, with 8.60 flags and u32 sprite ids
+    let profile: Profile = toml::from_str(
+        r#"
+        flags = "V860"
+        extended = true
+        pattern_depth = true
+        enhanced_animations = false
+        frame_groups = false
+        "#,
+    )
+    .unwrap();
+
+    let mut data = dat_header(0x1234_5678, 100);
+    data.extend_from_slice(&[16, 0xFF, 1, 1, 1, 1, 1, 1, 1]);
+    data.extend_from_slice(&0x0001_0000u32.to_le_bytes());
+
+    let dat = DatContainer::with_profile(&mut &data[..], profile).unwrap();
+    assert!(Client::from_dat_signature(dat.signature).is_none());
+    assert_eq!(vec![0x0001_0000], dat.items[0].sprite_ids);
+}
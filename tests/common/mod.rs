@@ -0,0 +1,458 @@
+//! Builders for the small OTBM and OTB files used by the round-trip tests.
+//! Everything is generated here, so no client or server files are needed.
+
+#![allow(dead_code)]
+
+use std::collections::BTreeMap;
+
+use mapeditor::map::Map;
+use mapeditor::opentibia::binaryfile::{Node, NodeWriter};
+use mapeditor::opentibia::itemtypes;
+use mapeditor::opentibia::map::{
+    CustomAttribute, Item, ItemAttribute, LoadOptions, Loader, Monster, Spawn, Tile, TileFlags,
+    Town, Waypoint,
+};
+use mapeditor::opentibia::Position;
+
+pub const STACKABLE_ID: u16 = 2148;
+pub const FLUID_ID: u16 = 2006;
+pub const SPLASH_ID: u16 = 2016;
+
+/// Every item attribute, with values chosen to contain the node marker bytes
+/// so that the writer has to escape them.
+pub fn all_attributes() -> Vec<ItemAttribute> {
+    let mut custom = BTreeMap::new();
+    custom.insert(
+        "string".to_string(),
+        CustomAttribute::String("\u{fe}\u{ff}".into()),
+    );
+    custom.insert(
+        "integer".to_string(),
+        CustomAttribute::Integer(-0x0102_FDFE),
+    );
+    custom.insert("float".to_string(), CustomAttribute::Float(1.5));
+    custom.insert("boolean".to_string(), CustomAttribute::Boolean(true));
+    custom.insert("double".to_string(), CustomAttribute::Double(-2.25));
+
+    vec![
+        ItemAttribute::Count(0xFE),
+        ItemAttribute::ActionId(0xFFFD),
+        ItemAttribute::UniqueId(0xFEFF),
+        ItemAttribute::Text("text \u{fd}".into()),
+        ItemAttribute::Description("description".into()),
+        ItemAttribute::Teleport(Position {
+            x: 0xFDFE,
+            y: 0x00FF,
+            z: 7,
+        }),
+        ItemAttribute::DepotId(0xFD),
+        ItemAttribute::HouseDoorId(0xFF),
+        ItemAttribute::Duration(-2),
+        ItemAttribute::DecayingState(0xFE),
+        ItemAttribute::WrittenDate(0xFEFD_FFFE),
+        ItemAttribute::WrittenBy("writer".into()),
+        ItemAttribute::SleeperGuid(0xFFFF_FFFF),
+        ItemAttribute::SleepStart(0xFD),
+        ItemAttribute::Charges(0xFFFE),
+        ItemAttribute::Name("name".into()),
+        ItemAttribute::Article("an".into()),
+        ItemAttribute::PluralName("names".into()),
+        ItemAttribute::Weight(0xFEFE),
+        ItemAttribute::Attack(-0xFF),
+        ItemAttribute::Defense(0xFD),
+        ItemAttribute::ExtraDefense(-1),
+        ItemAttribute::Armor(0xFF),
+        ItemAttribute::HitChance(0xFE),
+        ItemAttribute::ShootRange(0xFD),
+        ItemAttribute::Custom(custom),
+    ]
+}
+
+pub fn pos(x: u16, y: u16, z: u8) -> Position {
+    Position { x, y, z }
+}
+
+pub fn item(id: u16) -> Item {
+    Item {
+        id,
+        ..Default::default()
+    }
+}
+
+pub fn item_with(id: u16, attributes: Vec<ItemAttribute>) -> Item {
+    Item {
+        id,
+        attributes,
+        ..Default::default()
+    }
+}
+
+pub fn container(id: u16, children: Vec<Item>) -> Item {
+    Item {
+        id,
+        children,
+        ..Default::default()
+    }
+}
+
+/// Collects a map and the parts of a file that don't live on it yet.
+pub struct MapBuilder {
+    pub map: Map,
+    pub loader: Loader,
+}
+
+impl MapBuilder {
+    pub fn new(version: u32) -> MapBuilder {
+        let mut map = Map::new();
+        map.header_mut().version = version;
+
+        let mut loader = Loader::default();
+        loader.set_item_types(&item_types());
+
+        MapBuilder { map, loader }
+    }
+
+    pub fn tile(&mut self, pos: Position, items: Vec<Item>) -> &mut Tile {
+        let tile = self.map.get_or_create(&pos).get_tile(&pos);
+        tile.items = items;
+        tile
+    }
+
+    pub fn house_tile(&mut self, pos: Position, house_id: u32, items: Vec<Item>) -> &mut Tile {
+        let tile = self.tile(pos, items);
+        tile.house_id = Some(house_id);
+        tile
+    }
+
+    pub fn save(&mut self) -> Vec<u8> {
+        self.map.update_size().unwrap();
+
+        let mut data = Vec::new();
+        self.loader.save(&mut data, &self.map).unwrap();
+        data
+    }
+}
+
+/// A map using every node kind the writer produces: tiles and house tiles
+/// in several TileAreas and floors, items with every attribute, nested
+/// containers, spawns, towns and waypoints.
+pub fn sample_map(version: u32) -> MapBuilder {
+    let mut builder = MapBuilder::new(version);
+
+    {
+        let header = builder.map.header_mut();
+        header
+            .description
+            .push("Saved by the round-trip tests".into());
+        header.description.push("\u{fe}scaped".into());
+        header.spawn_file.push("sample-spawn.xml".into());
+        header.house_file.push("sample-house.xml".into());
+    }
+
+    let temple = Position {
+        x: 0x00FE,
+        y: 0x00FD,
+        z: 7,
+    };
+
+    builder.tile(temple, vec![item(0x01FE)]).flags.0 = TileFlags::PROTECTION_ZONE;
+
+    // Ground with attributes can't be stored inline
+    builder.tile(
+        Position {
+            x: 0x0100,
+            y: 0,
+            z: 7,
+        },
+        vec![item_with(0xFEFD, vec![ItemAttribute::ActionId(1)]), item(3)],
+    );
+
+    builder.tile(
+        Position {
+            x: 0x01FF,
+            y: 0xFEFF,
+            z: 0,
+        },
+        vec![item(4), item_with(5, all_attributes())],
+    );
+
+    builder.tile(
+        Position {
+            x: 12,
+            y: 34,
+            z: 15,
+        },
+        vec![
+            item(6),
+            container(
+                1988,
+                vec![
+                    container(
+                        1987,
+                        vec![item_with(STACKABLE_ID, vec![ItemAttribute::Count(100)])],
+                    ),
+                    item(7),
+                    container(1987, vec![]),
+                ],
+            ),
+            item_with(FLUID_ID, vec![ItemAttribute::Count(3)]),
+            item_with(SPLASH_ID, vec![ItemAttribute::Count(0xFE)]),
+        ],
+    );
+
+    let house_tile = builder.house_tile(
+        Position {
+            x: 300,
+            y: 20,
+            z: 6,
+        },
+        0xFEFDFF,
+        vec![
+            item(8),
+            item_with(1209, vec![ItemAttribute::HouseDoorId(1)]),
+        ],
+    );
+    house_tile.flags.set(TileFlags::NO_LOGOUT, true);
+    house_tile.flags.set(1 << 30, true);
+
+    // A house tile without items still has to be kept
+    builder.house_tile(
+        Position {
+            x: 301,
+            y: 20,
+            z: 6,
+        },
+        1,
+        vec![],
+    );
+
+    // As does a tile with only flags
+    builder
+        .tile(
+            Position {
+                x: 302,
+                y: 20,
+                z: 6,
+            },
+            vec![],
+        )
+        .flags
+        .0 = TileFlags::PVP_ZONE;
+
+    // Temples and waypoints have to be inside the size in the header
+    builder.map.update_size().unwrap();
+
+    builder
+        .map
+        .add_town(Town {
+            id: 0xFE,
+            name: "Thais".into(),
+            temple_position: temple,
+        })
+        .unwrap();
+
+    builder
+        .map
+        .add_town(Town {
+            id: 2,
+            name: "Carlin".into(),
+            temple_position: Position {
+                x: 300,
+                y: 20,
+                z: 6,
+            },
+        })
+        .unwrap();
+
+    if version >= 2 {
+        builder
+            .map
+            .add_waypoint(Waypoint {
+                name: "temple".into(),
+                position: temple,
+            })
+            .unwrap();
+    }
+
+    builder
+        .map
+        .add_spawn(Spawn {
+            center: Position {
+                x: 0x0100,
+                y: 0x00FF,
+                z: 7,
+            },
+            radius: 3,
+            monsters: vec![
+                Monster {
+                    name: "Rat".into(),
+                    offset: (-1, 2),
+                    spawn_time: 60,
+                },
+                Monster {
+                    name: "Cave Rat".into(),
+                    offset: (0, -3),
+                    spawn_time: 0xFE,
+                },
+            ],
+        })
+        .unwrap();
+
+    builder
+}
+
+/// Item types for version 0 maps, which store counts without an attribute
+/// for stackable, fluid and splash items.
+pub fn item_types() -> itemtypes::Container {
+    let data = OtbBuilder::new((3, 57, 0xFE))
+        .item(1, 0, 100, 200)
+        .item(5, 1 << 7, STACKABLE_ID, 3031)
+        .item(12, 0, FLUID_ID, 2874)
+        .item(11, 0, SPLASH_ID, 2886)
+        .build();
+
+    itemtypes::Container::new(&data[4..]).unwrap()
+}
+
+/// Builds an items.otb file out of nodes, in the layout written by the
+/// official item editor.
+pub struct OtbBuilder {
+    root: Node,
+}
+
+impl OtbBuilder {
+    const ATTR_VERSION: u8 = 1;
+    const ATTR_SERVER_ID: u8 = 0x10;
+    const ATTR_CLIENT_ID: u8 = 0x11;
+    const ATTR_NAME: u8 = 0x12;
+
+    pub fn new(version: (u32, u32, u32)) -> OtbBuilder {
+        let mut data = Vec::new();
+
+        // Flags
+        data.extend_from_slice(&0u32.to_le_bytes());
+
+        data.push(OtbBuilder::ATTR_VERSION);
+        data.extend_from_slice(&140u16.to_le_bytes());
+        data.extend_from_slice(&version.0.to_le_bytes());
+        data.extend_from_slice(&version.1.to_le_bytes());
+        data.extend_from_slice(&version.2.to_le_bytes());
+
+        let mut description = b"OTB round-trip test \xfe".to_vec();
+        description.resize(128, 0);
+        data.extend_from_slice(&description);
+
+        OtbBuilder {
+            root: Node {
+                kind: 0,
+                data,
+                children: Vec::new(),
+            },
+        }
+    }
+
+    pub fn item(mut self, group: u8, flags: u32, server_id: u16, client_id: u16) -> OtbBuilder {
+        let mut data = Vec::new();
+        data.extend_from_slice(&flags.to_le_bytes());
+
+        data.push(OtbBuilder::ATTR_SERVER_ID);
+        data.extend_from_slice(&2u16.to_le_bytes());
+        data.extend_from_slice(&server_id.to_le_bytes());
+
+        data.push(OtbBuilder::ATTR_CLIENT_ID);
+        data.extend_from_slice(&2u16.to_le_bytes());
+        data.extend_from_slice(&client_id.to_le_bytes());
+
+        // Windows-1252 text, padded with a NUL as some editors do
+        data.push(OtbBuilder::ATTR_NAME);
+        data.extend_from_slice(&4u16.to_le_bytes());
+        data.extend_from_slice(b"\xfd\xfe\xff\x00");
+
+        self.root.children.push(Node {
+            kind: group,
+            data,
+            children: Vec::new(),
+        });
+
+        self
+    }
+
+    /// Appends an attribute to the last item.
+    pub fn attribute(mut self, kind: u8, value: &[u8]) -> OtbBuilder {
+        let item = self.root.children.last_mut().unwrap();
+        item.data.push(kind);
+        item.data
+            .extend_from_slice(&(value.len() as u16).to_le_bytes());
+        item.data.extend_from_slice(value);
+
+        self
+    }
+
+    /// The file, starting with its 4 byte identifier.
+    pub fn build(&self) -> Vec<u8> {
+        let mut data = vec![0; 4];
+        self.root.serialize(&mut data).unwrap();
+        data
+    }
+}
+
+/// Loads a map with the streaming loader, failing on anything unexpected.
+pub fn load(data: &[u8]) -> (Loader, Map) {
+    let (loader, map) = load_with(data, LoadOptions::default());
+    assert!(loader.warnings.is_empty(), "{:?}", loader.warnings);
+
+    (loader, map)
+}
+
+pub fn load_with(data: &[u8], options: LoadOptions) -> (Loader, Map) {
+    let mut r = data;
+    let mut loader = Loader::open_with_options(&mut r, options).unwrap();
+    loader.set_item_types(&item_types());
+
+    let mut map = Map::new();
+
+    loader
+        .load(&mut r, |pos, tile| {
+            *map.get_or_create(&pos).get_tile(&pos) = tile
+        })
+        .unwrap();
+
+    loader.move_into(&mut map);
+
+    (loader, map)
+}
+
+/// All non-empty tiles of a map, in a stable order.
+pub fn tiles(map: &Map) -> Vec<(Position, Tile)> {
+    let mut tiles: Vec<_> = map.tiles().map(|(pos, tile)| (pos, tile.clone())).collect();
+
+    tiles.sort_by_key(|(pos, _)| (pos.z, pos.x, pos.y));
+    tiles
+}
+
+pub fn assert_maps_eq(a: &Map, b: &Map) {
+    assert_eq!(a.header(), b.header());
+    assert_eq!(tiles(a), tiles(b));
+    assert_eq!(a.towns().collect::<Vec<_>>(), b.towns().collect::<Vec<_>>());
+    assert_eq!(
+        a.waypoints().collect::<Vec<_>>(),
+        b.waypoints().collect::<Vec<_>>()
+    );
+    assert_eq!(
+        a.spawns().collect::<Vec<_>>(),
+        b.spawns().collect::<Vec<_>>()
+    );
+}
+
+/// Writes raw nodes, for files the map writer would never produce.
+pub fn write_nodes<F>(f: F) -> Vec<u8>
+where
+    F: FnOnce(&mut NodeWriter<&mut Vec<u8>>),
+{
+    let mut data = vec![0; 4];
+    let mut writer = NodeWriter::new(&mut data);
+    f(&mut writer);
+    assert_eq!(writer.depth(), 0);
+
+    data
+}
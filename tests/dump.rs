@@ -0,0 +1,144 @@
+//! Checks the node tree dumps of generated OTB and OTBM files.
+
+mod common;
+
+use serde_json::Value;
+
+use mapeditor::opentibia::dump::{self, DumpOptions, FileKind, Format};
+use mapeditor::opentibia::map::NodeKind;
+
+use common::*;
+
+fn dump_to_string(data: &[u8], options: &DumpOptions) -> String {
+    let mut out = Vec::new();
+    dump::dump(data, options, &mut out).unwrap();
+    String::from_utf8(out).unwrap()
+}
+
+#[test]
+fn dump_detects_the_file_kind() {
+    let otb = OtbBuilder::new((3, 57, 0)).item(1, 0, 100, 200).build();
+
+    assert_eq!(FileKind::Otb, FileKind::detect(&otb));
+    assert_eq!(FileKind::Otbm, FileKind::detect(&sample_map(2).save()));
+}
+
+#[test]
+fn dump_text_is_indented_by_depth() {
+    let data = sample_map(2).save();
+    let options = DumpOptions {
+        max_depth: Some(2),
+        ..Default::default()
+    };
+
+    let text = dump_to_string(&data, &options);
+    let lines: Vec<_> = text.lines().collect();
+
+    assert!(lines[0].starts_with("Root [0x00] @4 len=16 "));
+    assert!(lines[0].contains(" version=2 "));
+    assert!(lines[1].starts_with("  MapData [0x02] @23 "));
+    assert!(lines[2].starts_with("    TileArea [0x04] "));
+
+    // Nothing below the depth limit
+    assert!(lines.iter().all(|line| !line.starts_with("      ")));
+    assert!(lines.iter().any(|line| line.starts_with("    Towns ")));
+}
+
+#[test]
+fn dump_json_nests_the_filtered_nodes() {
+    let data = sample_map(2).save();
+    let options = DumpOptions {
+        format: Format::Json,
+        kinds: vec!["spawnarea".into(), "13".into()],
+        ..Default::default()
+    };
+
+    let nodes: Value = serde_json::from_str(&dump_to_string(&data, &options)).unwrap();
+    let nodes = nodes.as_array().unwrap();
+
+    let names: Vec<_> = nodes
+        .iter()
+        .map(|node| node["name"].as_str().unwrap())
+        .collect();
+    assert_eq!(vec!["SpawnArea", "Town", "Town"], names);
+
+    // Matching nodes come with their children
+    let monsters = nodes[0]["children"].as_array().unwrap();
+    assert_eq!(2, monsters.len());
+    assert_eq!("Rat", monsters[0]["decoded"]["name"]);
+    assert_eq!("Thais", nodes[1]["decoded"]["name"]);
+}
+
+#[test]
+fn dump_otb_decodes_item_types() {
+    let data = OtbBuilder::new((3, 57, 0))
+        .item(5, 1 << 7, STACKABLE_ID, 3031)
+        .build();
+
+    let text = dump_to_string(&data, &DumpOptions::default());
+    let lines: Vec<_> = text.lines().collect();
+
+    assert_eq!(2, lines.len());
+    assert!(lines[0].contains(" version=[3,57,0]"));
+    assert!(lines[1].starts_with("  Item [0x05] "));
+    assert!(lines[1].contains(&format!(" server_id={}", STACKABLE_ID)));
+}
+
+#[test]
+fn dump_shows_tiles_beyond_the_largest_coordinates_as_errors() {
+    let data = write_nodes(|w| {
+        w.begin(NodeKind::Root as u8).unwrap();
+        w.write_data(&[2, 0, 0, 0, 0, 1, 0, 1, 3, 0, 0, 0, 57, 0, 0, 0])
+            .unwrap();
+        w.begin(NodeKind::MapData as u8).unwrap();
+        w.begin(NodeKind::TileArea as u8).unwrap();
+        w.write_data(&[0xFF, 0xFF, 0, 1, 7]).unwrap();
+        w.begin(NodeKind::Tile as u8).unwrap();
+        w.write_data(&[5, 0]).unwrap();
+        w.end().unwrap();
+        w.end().unwrap();
+        w.end().unwrap();
+        w.end().unwrap();
+    });
+
+    let options = DumpOptions {
+        format: Format::Json,
+        kinds: vec!["tile".into()],
+        ..Default::default()
+    };
+
+    let nodes: Value = serde_json::from_str(&dump_to_string(&data, &options)).unwrap();
+    let error = nodes[0]["decoded"]["error"].as_str().unwrap();
+
+    assert!(error.contains("outside of the map"), "{}", error);
+}
+
+#[test]
+fn dump_json_stays_valid_after_a_parse_error() {
+    let data = sample_map(2).save();
+
+    // Inside of the data of the first Tile node
+    let tile_offset = data.windows(2).position(|w| w == [0xFE, 5]).unwrap();
+    let truncated = &data[..tile_offset + 3];
+
+    let options = DumpOptions {
+        format: Format::Json,
+        ..Default::default()
+    };
+
+    let mut out = Vec::new();
+    let err = dump::dump(truncated, &options, &mut out).unwrap_err();
+    let nodes: Value = serde_json::from_slice(&out).unwrap();
+
+    // The unfinished Tile is the error in the TileArea
+    let mut names = Vec::new();
+    let mut last = nodes.as_array().unwrap().last().unwrap();
+
+    while let Some(children) = last.get("children") {
+        names.push(last["name"].as_str().unwrap());
+        last = children.as_array().unwrap().last().unwrap();
+    }
+
+    assert_eq!(vec!["Root", "MapData", "TileArea"], names);
+    assert_eq!(err.to_string(), last["error"].as_str().unwrap());
+}
@@ -0,0 +1,186 @@
+//! Checks editing the towns and waypoints of a map.
+
+mod common;
+
+use mapeditor::map::{EditError, Map};
+use mapeditor::opentibia::map::{Spawn, Town, Waypoint};
+use mapeditor::opentibia::Position;
+
+use common::*;
+
+/// An empty 100x100 map, without any tiles.
+fn empty_map() -> Map {
+    let mut map = Map::new();
+    let header = map.header_mut();
+    header.width = 100;
+    header.height = 100;
+    map
+}
+
+fn town(id: u32, name: &str, temple_position: Position) -> Town {
+    Town {
+        id,
+        name: name.into(),
+        temple_position,
+    }
+}
+
+fn waypoint(name: &str, position: Position) -> Waypoint {
+    Waypoint {
+        name: name.into(),
+        position,
+    }
+}
+
+#[test]
+fn towns_can_be_added_renamed_moved_and_removed() {
+    let mut map = empty_map();
+
+    map.add_town(town(1, "Thais", pos(10, 10, 7))).unwrap();
+    map.add_town(town(2, "Carlin", pos(20, 20, 7))).unwrap();
+
+    map.rename_town(1, "Venore").unwrap();
+    map.move_town(2, pos(30, 40, 6)).unwrap();
+
+    assert_eq!(map.get_town(1), Some(&town(1, "Venore", pos(10, 10, 7))));
+    assert_eq!(map.get_town(2), Some(&town(2, "Carlin", pos(30, 40, 6))));
+
+    assert_eq!(map.remove_town(1), Ok(town(1, "Venore", pos(10, 10, 7))));
+    assert_eq!(map.get_town(1), None);
+    assert_eq!(map.towns().count(), 1);
+}
+
+#[test]
+fn towns_are_identified_by_their_id() {
+    let mut map = empty_map();
+    map.add_town(town(1, "Thais", pos(10, 10, 7))).unwrap();
+
+    assert_eq!(
+        map.add_town(town(1, "Carlin", pos(20, 20, 7))),
+        Err(EditError::DuplicateTown { id: 1 })
+    );
+    assert_eq!(map.towns().count(), 1);
+
+    let unknown = Err(EditError::UnknownTown { id: 2 });
+    assert_eq!(map.rename_town(2, "Carlin"), unknown);
+    assert_eq!(map.move_town(2, pos(20, 20, 7)), unknown);
+    assert_eq!(map.remove_town(2).map(|_| ()), unknown);
+}
+
+#[test]
+fn waypoints_can_be_added_renamed_moved_and_removed() {
+    let mut map = empty_map();
+
+    map.add_waypoint(waypoint("temple", pos(10, 10, 7)))
+        .unwrap();
+    map.add_waypoint(waypoint("depot", pos(20, 20, 7))).unwrap();
+
+    map.rename_waypoint("temple", "shrine").unwrap();
+    map.move_waypoint("depot", pos(30, 40, 6)).unwrap();
+
+    assert_eq!(map.get_waypoint("temple"), None);
+    assert_eq!(
+        map.get_waypoint("shrine"),
+        Some(&waypoint("shrine", pos(10, 10, 7)))
+    );
+    assert_eq!(
+        map.get_waypoint("depot"),
+        Some(&waypoint("depot", pos(30, 40, 6)))
+    );
+
+    assert_eq!(
+        map.remove_waypoint("shrine"),
+        Ok(waypoint("shrine", pos(10, 10, 7)))
+    );
+    assert_eq!(map.waypoints().count(), 1);
+}
+
+#[test]
+fn waypoints_are_identified_by_their_name() {
+    let mut map = empty_map();
+    map.add_waypoint(waypoint("temple", pos(10, 10, 7)))
+        .unwrap();
+    map.add_waypoint(waypoint("depot", pos(20, 20, 7))).unwrap();
+
+    let duplicate = Err(EditError::DuplicateWaypoint {
+        name: "temple".into(),
+    });
+    assert_eq!(
+        map.add_waypoint(waypoint("temple", pos(30, 30, 7))),
+        duplicate
+    );
+    assert_eq!(map.rename_waypoint("depot", "temple"), duplicate);
+
+    // Renaming a waypoint to its own name isn't a duplicate
+    map.rename_waypoint("temple", "temple").unwrap();
+
+    let unknown = Err(EditError::UnknownWaypoint {
+        name: "boat".into(),
+    });
+    assert_eq!(map.rename_waypoint("boat", "ship"), unknown);
+    assert_eq!(map.move_waypoint("boat", pos(30, 30, 7)), unknown);
+    assert_eq!(map.remove_waypoint("boat").map(|_| ()), unknown);
+}
+
+#[test]
+fn positions_outside_of_the_map_are_rejected() {
+    let mut map = empty_map();
+    map.add_town(town(1, "Thais", pos(10, 10, 7))).unwrap();
+    map.add_waypoint(waypoint("temple", pos(10, 10, 7)))
+        .unwrap();
+
+    for outside in [pos(100, 10, 7), pos(10, 100, 7), pos(10, 10, 16)] {
+        let error = Err(EditError::OutsideMap { position: outside });
+
+        assert_eq!(map.add_town(town(2, "Carlin", outside)), error);
+        assert_eq!(map.move_town(1, outside), error);
+        assert_eq!(map.add_waypoint(waypoint("depot", outside)), error);
+        assert_eq!(map.move_waypoint("temple", outside), error);
+    }
+
+    assert_eq!(map.get_town(1).unwrap().temple_position, pos(10, 10, 7));
+    assert_eq!(map.get_waypoint("temple").unwrap().position, pos(10, 10, 7));
+}
+
+#[test]
+fn empty_positions_inside_of_the_map_are_accepted() {
+    let mut builder = MapBuilder::new(2);
+    builder.tile(pos(10, 10, 7), vec![item(STACKABLE_ID)]);
+    builder.map.update_size().unwrap();
+
+    // Neither position has a tile, but both are within the header's size
+    let map = &mut builder.map;
+    map.add_town(town(1, "Thais", pos(5, 5, 0))).unwrap();
+    map.add_waypoint(waypoint("temple", pos(0, 0, 15))).unwrap();
+
+    assert_eq!(map.header().width, 11);
+    assert_eq!(map.header().height, 11);
+}
+
+#[test]
+fn spawns_can_be_added_and_removed() {
+    let mut map = empty_map();
+    let spawn = |center| Spawn {
+        center,
+        radius: 3,
+        monsters: vec![],
+    };
+
+    map.add_spawn(spawn(pos(10, 10, 7))).unwrap();
+    assert_eq!(
+        map.add_spawn(spawn(pos(100, 10, 7))),
+        Err(EditError::OutsideMap {
+            position: pos(100, 10, 7)
+        })
+    );
+    assert_eq!(1, map.spawns().count());
+
+    assert_eq!(
+        map.remove_spawn(pos(20, 20, 7)),
+        Err(EditError::UnknownSpawn {
+            center: pos(20, 20, 7)
+        })
+    );
+    assert_eq!(map.remove_spawn(pos(10, 10, 7)), Ok(spawn(pos(10, 10, 7))));
+    assert_eq!(0, map.spawns().count());
+}
@@ -0,0 +1,113 @@
+//! Checks the errors the OTBM loader returns for broken maps, and loading
+//! them leniently.
+
+mod common;
+
+use std::io;
+
+use mapeditor::opentibia::binaryfile::NodeWriter;
+use mapeditor::opentibia::map::{LoadOptions, Loader, MapError, NodeKind};
+
+use common::*;
+
+/// A map of version 2 whose MapData node holds the nodes written by `f`.
+fn map_with<F>(f: F) -> Vec<u8>
+where
+    F: FnOnce(&mut NodeWriter<&mut Vec<u8>>),
+{
+    write_nodes(|w| {
+        w.begin(NodeKind::Root as u8).unwrap();
+        w.write_data(&[2, 0, 0, 0, 0, 1, 0, 1, 3, 0, 0, 0, 57, 0, 0, 0])
+            .unwrap();
+        w.begin(NodeKind::MapData as u8).unwrap();
+        f(w);
+        w.end().unwrap();
+        w.end().unwrap();
+    })
+}
+
+fn tile_area_node(w: &mut NodeWriter<&mut Vec<u8>>) {
+    w.begin(NodeKind::TileArea as u8).unwrap();
+    w.write_data(&[0, 1, 0, 1, 7]).unwrap();
+    tile_node(w);
+    w.end().unwrap();
+}
+
+// A tile with a ground item
+fn tile_node(w: &mut NodeWriter<&mut Vec<u8>>) {
+    w.begin(NodeKind::Tile as u8).unwrap();
+    w.write_data(&[1, 2, 9, 100, 0]).unwrap();
+    w.end().unwrap();
+}
+
+fn item_node(w: &mut NodeWriter<&mut Vec<u8>>) {
+    w.begin(NodeKind::Item as u8).unwrap();
+    w.write_data(&[100, 0]).unwrap();
+    w.end().unwrap();
+}
+
+fn unknown_node(w: &mut NodeWriter<&mut Vec<u8>>) {
+    w.begin(0x42).unwrap();
+    w.end().unwrap();
+}
+
+/// Loads `data`, returning the number of tiles loaded and the loader.
+fn load_map(data: &[u8], options: LoadOptions) -> io::Result<(usize, Loader)> {
+    let mut r = data;
+    let mut loader = Loader::open_with_options(&mut r, options)?;
+    let mut tiles = 0;
+
+    loader.load(&mut r, |_, _| tiles += 1)?;
+
+    Ok((tiles, loader))
+}
+
+fn load_error(data: &[u8]) -> MapError {
+    let err = load_map(data, LoadOptions::default()).unwrap_err();
+    *err.into_inner().unwrap().downcast::<MapError>().unwrap()
+}
+
+#[test]
+fn broken_nodes_are_errors() {
+    let error = load_error(&map_with(unknown_node));
+    assert!(matches!(error, MapError::UnknownNode { kind: 0x42, .. }));
+
+    let error = load_error(&map_with(tile_node));
+    assert!(matches!(
+        error,
+        MapError::TileOutsideTileArea {
+            kind: NodeKind::Tile,
+            ..
+        }
+    ));
+
+    let error = load_error(&map_with(item_node));
+    assert!(matches!(error, MapError::ItemOutsideTile { .. }));
+}
+
+#[test]
+fn broken_nodes_are_skipped_when_lenient() {
+    let data = map_with(|w| {
+        unknown_node(w);
+        item_node(w);
+        tile_node(w);
+        tile_area_node(w);
+    });
+
+    let options = LoadOptions {
+        lenient: true,
+        ..Default::default()
+    };
+
+    let (tiles, loader) = load_map(&data, options).unwrap();
+
+    assert_eq!(1, tiles);
+    assert!(matches!(
+        loader.warnings[..],
+        [
+            MapError::UnknownNode { kind: 0x42, .. },
+            MapError::ItemOutsideTile { .. },
+            MapError::TileOutsideTileArea { .. },
+        ]
+    ));
+}
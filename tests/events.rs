@@ -0,0 +1,265 @@
+//! Checks the events emitted while walking node trees, and that the map
+//! loader relies on them for the parts of a map that depend on nesting.
+
+mod common;
+
+use std::io;
+
+use mapeditor::opentibia::binaryfile::{EventParser, NodeEvent, ParseAction};
+use mapeditor::opentibia::map::{LoadOptions, Loader, MapError, NodeKind};
+
+use common::*;
+
+#[derive(Debug, PartialEq)]
+enum Event {
+    Enter(u8, Vec<u8>, Vec<u8>),
+    Exit(u8, Vec<u8>),
+}
+
+fn record(offset: u64, path: &[u8], event: NodeEvent, events: &mut Vec<(u64, Event)>) {
+    let event = match event {
+        NodeEvent::Enter { kind, data } => Event::Enter(kind, data.to_vec(), path.to_vec()),
+        NodeEvent::Exit { kind } => Event::Exit(kind, path.to_vec()),
+    };
+
+    events.push((offset, event));
+}
+
+#[test]
+fn events_follow_the_node_tree() {
+    let data = write_nodes(|w| {
+        w.begin(1).unwrap();
+        w.write_data(&[0xFE]).unwrap();
+        w.begin(2).unwrap();
+        w.begin(3).unwrap();
+        w.write_data(&[7]).unwrap();
+        w.end().unwrap();
+        w.end().unwrap();
+        w.begin(4).unwrap();
+        w.end().unwrap();
+        w.end().unwrap();
+    });
+
+    let expected = vec![
+        (4, Event::Enter(1, vec![0xFE], vec![])),
+        (8, Event::Enter(2, vec![], vec![1])),
+        (10, Event::Enter(3, vec![7], vec![1, 2])),
+        (13, Event::Exit(3, vec![1, 2])),
+        (14, Event::Exit(2, vec![1])),
+        (15, Event::Enter(4, vec![], vec![1])),
+        (17, Event::Exit(4, vec![1])),
+        (18, Event::Exit(1, vec![])),
+    ];
+
+    let mut streamed = Vec::new();
+    EventParser::new(4, Vec::new())
+        .parse(&data[4..], |position, path, event| {
+            record(position.offset, path, event, &mut streamed);
+            Ok(ParseAction::Continue)
+        })
+        .unwrap();
+
+    assert_eq!(expected, streamed);
+
+    let mut sliced = Vec::new();
+    EventParser::new(4, Vec::new())
+        .parse_slice(&data, |position, path, event| {
+            record(position.offset, path, event, &mut sliced);
+            Ok(ParseAction::Continue)
+        })
+        .unwrap();
+
+    assert_eq!(expected, sliced);
+
+    // Skipped children produce no events, but the node is still exited
+    let mut skipped = Vec::new();
+    EventParser::new(4, Vec::new())
+        .parse_slice(&data, |position, path, event| {
+            record(position.offset, path, event, &mut skipped);
+
+            Ok(match event {
+                NodeEvent::Enter { kind: 2, .. } => ParseAction::SkipChildren,
+                _ => ParseAction::Continue,
+            })
+        })
+        .unwrap();
+
+    assert_eq!(
+        vec![&expected[0], &expected[1], &expected[4], &expected[5]],
+        skipped.iter().take(4).collect::<Vec<_>>()
+    );
+
+    // Stopping keeps the state needed to resume on the rest of the data
+    let mut parser = EventParser::new(4, Vec::new());
+    let mut resumed = Vec::new();
+
+    parser
+        .parse(&data[4..], |position, path, event| {
+            record(position.offset, path, event, &mut resumed);
+            Ok(ParseAction::Stop)
+        })
+        .unwrap();
+
+    assert_eq!(vec![1], parser.path());
+
+    parser
+        .parse_slice(&data, |position, path, event| {
+            record(position.offset, path, event, &mut resumed);
+            Ok(ParseAction::Continue)
+        })
+        .unwrap();
+
+    assert_eq!(expected, resumed);
+}
+
+#[test]
+fn otbm_monsters_need_an_enclosing_spawn_area() {
+    let data = write_nodes(|w| {
+        w.begin(NodeKind::Root as u8).unwrap();
+        w.write_data(&[2, 0, 0, 0, 0, 1, 0, 1, 3, 0, 0, 0, 57, 0, 0, 0])
+            .unwrap();
+        w.begin(NodeKind::MapData as u8).unwrap();
+
+        w.begin(NodeKind::Spawns as u8).unwrap();
+        w.begin(NodeKind::SpawnArea as u8).unwrap();
+        w.write_data(&[0, 1, 0, 1, 7, 2, 0, 0, 0]).unwrap();
+        w.end().unwrap();
+
+        // A sibling of the SpawnArea, not inside of it
+        w.begin(NodeKind::Monster as u8).unwrap();
+        w.write_data(&[3, 0, b'R', b'a', b't', 0, 0, 0, 0, 60, 0, 0, 0])
+            .unwrap();
+        w.end().unwrap();
+        w.end().unwrap();
+
+        w.end().unwrap();
+        w.end().unwrap();
+    });
+
+    let options = LoadOptions {
+        lenient: true,
+        ..Default::default()
+    };
+
+    let mut loader = Loader::open_with_options(&data[..], options).unwrap();
+    loader.load_slice(&data, |_, _| ()).unwrap();
+
+    assert_eq!(1, loader.spawns.len());
+    assert!(loader.spawns[0].monsters.is_empty());
+    assert!(matches!(
+        loader.warnings[..],
+        [MapError::UnexpectedNode {
+            kind: NodeKind::Monster,
+            ..
+        }]
+    ));
+}
+
+#[test]
+fn otbm_tiles_beyond_the_largest_coordinates_are_errors() {
+    let data = write_nodes(|w| {
+        w.begin(NodeKind::Root as u8).unwrap();
+        w.write_data(&[2, 0, 0, 0, 0, 1, 0, 1, 3, 0, 0, 0, 57, 0, 0, 0])
+            .unwrap();
+        w.begin(NodeKind::MapData as u8).unwrap();
+
+        w.begin(NodeKind::TileArea as u8).unwrap();
+        w.write_data(&[0xFF, 0xFF, 0, 1, 7]).unwrap();
+        w.begin(NodeKind::Tile as u8).unwrap();
+        w.write_data(&[5, 0]).unwrap();
+        w.end().unwrap();
+        w.end().unwrap();
+
+        // Not written by any known editor
+        w.begin(NodeKind::ItemDef as u8).unwrap();
+        w.end().unwrap();
+
+        w.end().unwrap();
+        w.end().unwrap();
+    });
+
+    let mut loader = Loader::open(&data[..]).unwrap();
+    let err = loader.load_slice(&data, |_, _| ()).unwrap_err();
+    let err = err.into_inner().unwrap().downcast::<MapError>().unwrap();
+
+    assert_eq!(
+        MapError::TileOutsideMap {
+            offset: 33,
+            kind: NodeKind::Tile,
+            origin: pos(0xFFFF, 0x0100, 7),
+            area_offset: (5, 0),
+        },
+        *err
+    );
+
+    let options = LoadOptions {
+        lenient: true,
+        ..Default::default()
+    };
+
+    let mut loader = Loader::open_with_options(&data[..], options).unwrap();
+    let mut tiles = 0;
+    loader.load_slice(&data, |_, _| tiles += 1).unwrap();
+
+    assert_eq!(0, tiles);
+    assert!(matches!(
+        loader.warnings[..],
+        [
+            MapError::TileOutsideMap { .. },
+            MapError::UnexpectedNode {
+                kind: NodeKind::ItemDef,
+                ..
+            }
+        ]
+    ));
+}
+
+#[test]
+fn otbm_truncated_files_are_errors() {
+    let data = sample_map(2).save();
+
+    // Inside of the data of the first Tile node
+    let tile_offset = data.windows(2).position(|w| w == [0xFE, 5]).unwrap();
+    let truncated = &data[..tile_offset + 3];
+
+    let mut r = truncated;
+    let mut loader = Loader::open(&mut r).unwrap();
+    loader.set_item_types(&item_types());
+    let err = loader.load(&mut r, |_, _| ()).unwrap_err();
+    assert_eq!(io::ErrorKind::UnexpectedEof, err.kind());
+
+    let mut loader = Loader::open(truncated).unwrap();
+    loader.set_item_types(&item_types());
+    let err = loader.load_slice(truncated, |_, _| ()).unwrap_err();
+    assert_eq!(io::ErrorKind::UnexpectedEof, err.kind());
+
+    // Missing the end of Root, or of MapData and Root
+    for missing in 1..=2 {
+        let truncated = &data[..data.len() - missing];
+
+        let mut loader = Loader::open(truncated).unwrap();
+        loader.set_item_types(&item_types());
+        let err = loader.load_slice(truncated, |_, _| ()).unwrap_err();
+        assert_eq!(io::ErrorKind::UnexpectedEof, err.kind());
+
+        let mut loader = Loader::open(truncated).unwrap();
+        loader.set_item_types(&item_types());
+        let err = loader.load_parallel(truncated, 2).unwrap_err();
+        assert_eq!(io::ErrorKind::UnexpectedEof, err.kind());
+    }
+}
+
+#[test]
+fn otbm_nodes_after_the_root_are_errors() {
+    let mut data = sample_map(2).save();
+    data.extend_from_slice(&[0xFE, 5, 0, 0, 0xFF]);
+
+    let mut loader = Loader::open(&data[..]).unwrap();
+    loader.set_item_types(&item_types());
+    assert!(loader.load_slice(&data, |_, _| ()).is_err());
+
+    let mut loader = Loader::open(&data[..]).unwrap();
+    loader.set_item_types(&item_types());
+    let err = loader.load_parallel(&data, 2).unwrap_err();
+    assert_eq!(io::ErrorKind::InvalidInput, err.kind());
+}
@@ -0,0 +1,118 @@
+//! Checks loading only part of a map, limited to a region or to some floors.
+
+mod common;
+
+use mapeditor::opentibia::map::{LoadOptions, Loader, Region};
+use mapeditor::opentibia::Position;
+
+use common::*;
+
+/// One tile in each of four TileAreas, two of them on the same floor.
+fn four_areas() -> Vec<u8> {
+    let mut builder = MapBuilder::new(2);
+
+    for pos in [
+        pos(10, 10, 7),
+        pos(300, 10, 7),
+        pos(300, 300, 7),
+        pos(10, 10, 6),
+    ] {
+        builder.tile(pos, vec![item(STACKABLE_ID)]);
+    }
+
+    builder.save()
+}
+
+/// Loads `data` with every loader, checking they load the same tiles and
+/// skip the same areas.
+fn load_filtered(data: &[u8], options: LoadOptions) -> (Vec<Position>, usize) {
+    let (loader, map) = load_with(data, options.clone());
+    let positions: Vec<_> = tiles(&map).into_iter().map(|(pos, _)| pos).collect();
+
+    for threads in [1, 3] {
+        let mut parallel_loader = Loader::open_with_options(data, options.clone()).unwrap();
+        parallel_loader.set_item_types(&item_types());
+
+        let parallel = parallel_loader.load_parallel(data, threads).unwrap();
+        assert_maps_eq(&map, &parallel);
+        assert_eq!(loader.skipped_areas, parallel_loader.skipped_areas);
+    }
+
+    (positions, loader.skipped_areas)
+}
+
+#[test]
+fn everything_is_loaded_without_filters() {
+    let (positions, skipped_areas) = load_filtered(&four_areas(), LoadOptions::default());
+
+    assert_eq!(4, positions.len());
+    assert_eq!(0, skipped_areas);
+}
+
+#[test]
+fn only_tiles_inside_of_the_region_are_loaded() {
+    let options = LoadOptions {
+        region: Some(Region {
+            min_x: 250,
+            min_y: 0,
+            max_x: 350,
+            max_y: 50,
+        }),
+        ..Default::default()
+    };
+
+    let (positions, skipped_areas) = load_filtered(&four_areas(), options);
+    assert_eq!(vec![pos(300, 10, 7)], positions);
+
+    // The areas at x 0 overlap the region and are read tile by tile, only
+    // the one at y 256 is skipped as a whole
+    assert_eq!(1, skipped_areas);
+}
+
+#[test]
+fn only_tiles_on_the_chosen_floors_are_loaded() {
+    let options = LoadOptions {
+        floors: Some(vec![6]),
+        ..Default::default()
+    };
+
+    let (positions, skipped_areas) = load_filtered(&four_areas(), options);
+    assert_eq!(vec![pos(10, 10, 6)], positions);
+    assert_eq!(3, skipped_areas);
+}
+
+#[test]
+fn region_and_floors_are_combined() {
+    let options = LoadOptions {
+        region: Some(Region {
+            min_x: 0,
+            min_y: 0,
+            max_x: 20,
+            max_y: 20,
+        }),
+        floors: Some(vec![7]),
+        ..Default::default()
+    };
+
+    let (positions, skipped_areas) = load_filtered(&four_areas(), options);
+    assert_eq!(vec![pos(10, 10, 7)], positions);
+    assert_eq!(3, skipped_areas);
+}
+
+#[test]
+fn filters_keep_towns_and_waypoints() {
+    let data = sample_map(2).save();
+    let (_, expected) = load(&data);
+
+    let options = LoadOptions {
+        floors: Some(vec![]),
+        ..Default::default()
+    };
+    let (loader, map) = load_with(&data, options);
+
+    assert!(tiles(&map).is_empty());
+    assert!(loader.skipped_areas > 0);
+    assert!(map.towns().eq(expected.towns()));
+    assert!(map.waypoints().eq(expected.waypoints()));
+    assert!(map.spawns().eq(expected.spawns()));
+}
@@ -0,0 +1,74 @@
+//! Checks editing the root and MapData header of a map.
+
+mod common;
+
+use mapeditor::map::{EditError, Map};
+
+use common::*;
+
+#[test]
+fn items_version_has_to_match_the_loaded_item_types() {
+    // The item types are 3.57
+    let items = item_types();
+    let mut map = Map::new();
+
+    map.header_mut().set_items_version((3, 57), &items).unwrap();
+    map.header_mut().set_items_version((3, 20), &items).unwrap();
+    assert_eq!((3, 20), map.header().items_version());
+
+    for items_version in [(2, 20), (4, 20), (3, 58)] {
+        assert_eq!(
+            map.header_mut().set_items_version(items_version, &items),
+            Err(EditError::IncompatibleItemsVersion {
+                items_version,
+                loaded: (3, 57),
+            })
+        );
+    }
+
+    assert_eq!((3, 20), map.header().items_version());
+}
+
+#[test]
+fn items_version_is_saved() {
+    let mut builder = sample_map(2);
+    builder
+        .map
+        .header_mut()
+        .set_items_version((3, 20), &item_types())
+        .unwrap();
+
+    let (_, map) = load(&builder.save());
+    assert_eq!((3, 20), map.header().items_version());
+}
+
+#[test]
+fn size_fits_all_tiles() {
+    let mut builder = MapBuilder::new(2);
+
+    builder.map.update_size().unwrap();
+    assert_eq!(
+        (0, 0),
+        (builder.map.header().width, builder.map.header().height)
+    );
+
+    builder.tile(pos(300, 20, 7), vec![item(STACKABLE_ID)]);
+    builder.tile(pos(10, 500, 0), vec![item(STACKABLE_ID)]);
+    // Empty tiles don't count
+    builder.tile(pos(1000, 1000, 7), vec![]);
+
+    builder.map.update_size().unwrap();
+    assert_eq!(
+        (301, 501),
+        (builder.map.header().width, builder.map.header().height)
+    );
+
+    // A tile at the highest coordinate saturates instead of overflowing
+    builder.tile(pos(u16::MAX, 0, 7), vec![item(STACKABLE_ID)]);
+
+    builder.map.update_size().unwrap();
+    assert_eq!(
+        (u16::MAX, 501),
+        (builder.map.header().width, builder.map.header().height)
+    );
+}
@@ -0,0 +1,78 @@
+mod common;
+
+use mapeditor::opentibia::itemsxml::{self, ItemsXmlWarning};
+
+use common::*;
+
+const ITEMS_XML: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<items>
+    <item id="100" name="void" />
+    <item id="2148" article="a" name="gold coin" plural="gold coins">
+        <attribute key="weight" value="10" />
+        <attribute key="Description" value="Shiny." />
+    </item>
+    <item fromid="2016" toid="2017" name="blood">
+        <attribute key="type" value="splash" />
+        <attribute key="field" value="fire">
+            <attribute key="damage" value="20" />
+            <attribute key="ticks" value="4000" />
+        </attribute>
+    </item>
+    <item id="2148" name="copper coin" />
+</items>
+"#;
+
+#[test]
+fn items_xml_attaches_metadata_by_server_id() {
+    let mut items = item_types();
+    let warnings = itemsxml::load(ITEMS_XML.as_bytes(), &mut items).unwrap();
+
+    let coin = items.items[STACKABLE_ID as usize]
+        .metadata
+        .as_ref()
+        .unwrap();
+    assert_eq!(Some("gold coin"), coin.name.as_deref());
+    assert_eq!(Some("a"), coin.article.as_deref());
+    assert_eq!(Some("gold coins"), coin.plural.as_deref());
+    assert_eq!(Some("Shiny."), coin.description.as_deref());
+    assert_eq!("10", coin.attribute("WEIGHT").unwrap().value);
+    assert_eq!(
+        Some("gold coin"),
+        items.items[STACKABLE_ID as usize].display_name()
+    );
+
+    let blood = items.items[SPLASH_ID as usize].metadata.as_ref().unwrap();
+    assert_eq!(Some("splash"), blood.kind.as_deref());
+
+    let field = blood.attribute("field").unwrap();
+    assert_eq!("fire", field.value);
+    assert_eq!("20", field.get("damage").unwrap().value);
+    assert_eq!(2, field.children.len());
+
+    assert!(warnings.contains(&ItemsXmlWarning::DuplicateEntry { server_id: 2148 }));
+    assert!(warnings.contains(&ItemsXmlWarning::MissingFromOtb { server_id: 2017 }));
+    assert!(warnings.contains(&ItemsXmlWarning::NameMismatch {
+        server_id: 100,
+        otb: "\u{fd}\u{fe}\u{ff}".into(),
+        xml: "void".into(),
+    }));
+    // The builder names every item, so each named entry is a mismatch
+    assert_eq!(5, warnings.len());
+}
+
+#[test]
+fn items_xml_reads_latin1_and_rejects_bad_ids() {
+    let mut items = item_types();
+    let data = b"<items><item id=\"2148\" name=\"p\xe9\xe7a\"/></items>";
+    itemsxml::load(&data[..], &mut items).unwrap();
+    assert_eq!(
+        Some("p\u{e9}\u{e7}a"),
+        items.items[STACKABLE_ID as usize].display_name()
+    );
+
+    let data = "<items><item fromid=\"5\" toid=\"4\"/></items>";
+    assert!(itemsxml::load(data.as_bytes(), &mut items).is_err());
+
+    let data = "<items><item id=\"gold\"/></items>";
+    assert!(itemsxml::load(data.as_bytes(), &mut items).is_err());
+}
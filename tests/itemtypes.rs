@@ -0,0 +1,293 @@
+mod common;
+
+use mapeditor::opentibia::binaryfile::Node;
+use mapeditor::opentibia::itemtypes::{self, ItemFlags, ItemGroup, Light};
+use mapeditor::opentibia::RawAttribute;
+
+use common::*;
+
+#[test]
+fn otb_items_keep_every_attribute() {
+    let flags = ItemFlags::BLOCK_SOLID | ItemFlags::PICKUPABLE | ItemFlags::ALWAYS_ON_TOP;
+    let data = OtbBuilder::new((3, 57, 0))
+        .item(2, flags | 1 << 31, 1987, 1988)
+        .attribute(0x12, b"bag")
+        .attribute(0x13, b"A bag \xe9.")
+        .attribute(0x14, &220u16.to_le_bytes())
+        .attribute(0x20, &[7; 16])
+        .attribute(0x21, &24u16.to_le_bytes())
+        .attribute(0x22, &[1, 0])
+        .attribute(0x23, &[2, 0])
+        .attribute(0x2A, &[3, 0, 215, 0])
+        .attribute(0x2B, &[1])
+        .attribute(0x2D, &[5, 0])
+        .attribute(0x15, &[9, 9])
+        .build();
+
+    let items = itemtypes::Container::new(&data[4..]).unwrap();
+    let bag = &items.items[1987];
+
+    assert_eq!(ItemGroup::Container, bag.group);
+    assert!(bag.flags.blocks_solid() && bag.flags.pickupable() && bag.flags.always_on_top());
+    assert!(!bag.flags.stackable());
+    assert!(bag.flags.contains(1 << 31));
+
+    assert_eq!(Some(1988), bag.client_id);
+    assert_eq!(Some("A bag \u{e9}."), bag.description.as_deref());
+    assert_eq!(Some(220), bag.speed);
+    assert_eq!(Some([7; 16]), bag.sprite_hash);
+    assert_eq!(Some(24), bag.minimap_color);
+    assert_eq!(
+        (Some(1), Some(2)),
+        (bag.max_read_write_chars, bag.max_read_chars)
+    );
+    assert_eq!(
+        Some(Light {
+            level: 3,
+            color: 215
+        }),
+        bag.light
+    );
+    assert_eq!(Some(1), bag.top_order);
+    assert_eq!(Some(5), bag.ware_id);
+
+    // Only the builder's name is kept; the repeated one stays raw
+    assert_eq!(Some("\u{fd}\u{fe}\u{ff}\0"), bag.name.as_deref());
+    assert_eq!(
+        vec![
+            RawAttribute {
+                kind: 0x12,
+                data: b"bag".to_vec()
+            },
+            RawAttribute {
+                kind: 0x15,
+                data: vec![9, 9]
+            },
+        ],
+        bag.unknown_attributes
+    );
+}
+
+#[test]
+fn otb_items_keep_unknown_groups() {
+    let data = OtbBuilder::new((3, 57, 0)).item(0xF0, 0, 100, 100).build();
+    let items = itemtypes::Container::new(&data[4..]).unwrap();
+    assert_eq!(ItemGroup::Unknown(0xF0), items.items[100].group);
+
+    let mut saved = vec![0; 4];
+    items.serialize(&mut saved).unwrap();
+    assert!(data == saved);
+}
+
+#[test]
+fn otb_items_reject_truncated_attributes() {
+    let mut data = OtbBuilder::new((3, 57, 0)).item(1, 0, 100, 100).build();
+    // Cut the item's name short, keeping the node end markers
+    let end = data.len() - 2;
+    data.drain(end - 2..end);
+    assert!(itemtypes::Container::new(&data[4..]).is_err());
+}
+
+#[test]
+fn otb_files_are_byte_exact_after_save() {
+    let data = OtbBuilder::new((3, 57, 0xFE))
+        .item(14, 0, 0x100, 0x200)
+        .item(11, 0, SPLASH_ID, 2886)
+        .attribute(0x19, &[1, 2, 3])
+        .item(1, 0xFFFF_FFFF, 0xFFFF, 0xFDFD)
+        .attribute(0x20, &[0xFE; 16])
+        .attribute(0x2A, &[0xFF, 0, 0xFD, 0])
+        .build();
+
+    // Item types are saved ordered by server id, like the file was
+    let items = itemtypes::Container::new(&data[4..]).unwrap();
+
+    let mut saved = vec![0; 4];
+    items.serialize(&mut saved).unwrap();
+
+    assert!(data == saved);
+}
+
+/// Serializes a root node with the given data and item nodes, starting with
+/// the file identifier.
+fn otb_file(root: Vec<u8>, items: Vec<(u8, Vec<u8>)>) -> Vec<u8> {
+    let node = Node {
+        kind: 0,
+        data: root,
+        children: items
+            .into_iter()
+            .map(|(kind, data)| Node {
+                kind,
+                data,
+                children: Vec::new(),
+            })
+            .collect(),
+    };
+
+    let mut data = vec![0; 4];
+    node.serialize(&mut data).unwrap();
+    data
+}
+
+#[test]
+fn otb_files_keep_their_layout_after_save() {
+    // A description with leftovers after its NUL, and an unknown attribute
+    let mut root = vec![0; 4];
+    root.extend_from_slice(&[1, 140, 0]);
+    root.extend([3, 57, 0xFE].iter().flat_map(|v: &u32| v.to_le_bytes()));
+    let mut description = b"old\0items editor leftovers".to_vec();
+    description.resize(128, 0xCC);
+    root.extend_from_slice(&description);
+    root.extend_from_slice(&[0x7F, 2, 0, 0xAB, 0xCD]);
+
+    // Attributes out of the order of their kinds, and one without a server id
+    let mut unordered = vec![0; 4];
+    unordered.extend_from_slice(&[0x11, 2, 0, 0xC8, 0]);
+    unordered.extend_from_slice(&[0x15, 1, 0, 9]);
+    unordered.extend_from_slice(&[0x10, 2, 0, 100, 0]);
+    unordered.extend_from_slice(&[0x14, 2, 0, 150, 0]);
+    let no_server_id = vec![0, 0, 0, 0, 0x11, 2, 0, 0xC9, 0];
+
+    let data = otb_file(root, vec![(1, no_server_id), (1, unordered)]);
+    let items = itemtypes::Container::new(&data[4..]).unwrap();
+    assert_eq!("old", items.description);
+
+    let mut saved = vec![0; 4];
+    items.serialize(&mut saved).unwrap();
+    assert!(data == saved);
+
+    // Without a version attribute, none is added
+    let data = otb_file(vec![0; 4], vec![]);
+    let items = itemtypes::Container::new(&data[4..]).unwrap();
+
+    let mut saved = vec![0; 4];
+    items.serialize(&mut saved).unwrap();
+    assert!(data == saved);
+}
+
+#[test]
+fn otb_edited_attributes_are_written_in_place() {
+    let data = OtbBuilder::new((3, 57, 0))
+        .item(1, 0, 100, 200)
+        .attribute(0x14, &150u16.to_le_bytes())
+        .build();
+
+    let mut items = itemtypes::Container::new(&data[4..]).unwrap();
+    items.description = "edited".into();
+    let item = &mut items.items[100];
+    item.client_id = None;
+    item.speed = Some(200);
+    item.ware_id = Some(7);
+
+    let mut saved = Vec::new();
+    items.serialize(&mut saved).unwrap();
+
+    let root = Node::deserialize(&mut &saved[..], false).unwrap();
+    let kinds: Vec<_> = itemtypes_attributes(&root.children[0].data[4..])
+        .iter()
+        .map(|&(kind, _)| kind)
+        .collect();
+
+    // The client id is gone, the speed stays after the name and the ware id
+    // is added at the end
+    assert_eq!(vec![0x10, 0x12, 0x14, 0x2D], kinds);
+    assert_eq!(
+        "edited",
+        itemtypes::Container::new(&saved[..]).unwrap().description
+    );
+}
+
+/// Splits the attributes of an item node after its flags.
+fn itemtypes_attributes(mut data: &[u8]) -> Vec<(u8, Vec<u8>)> {
+    let mut attributes = Vec::new();
+
+    while !data.is_empty() {
+        let len = u16::from_le_bytes([data[1], data[2]]) as usize;
+        attributes.push((data[0], data[3..3 + len].to_vec()));
+        data = &data[3 + len..];
+    }
+
+    attributes
+}
+
+#[test]
+fn otb_item_types_can_be_edited() {
+    let mut items = item_types();
+    let server_id = items.next_server_id().unwrap();
+    assert!(server_id > SPLASH_ID);
+
+    let custom = itemtypes::Item {
+        server_id,
+        client_id: Some(5000),
+        group: ItemGroup::Ground,
+        flags: ItemFlags(ItemFlags::BLOCK_SOLID),
+        name: Some("custom floor".into()),
+        speed: Some(150),
+        ..Default::default()
+    };
+    items.add(custom.clone()).unwrap();
+
+    let duplicate = itemtypes::Item {
+        server_id: STACKABLE_ID,
+        ..Default::default()
+    };
+    assert!(items.add(duplicate).is_err());
+
+    items.set_client_id(server_id, Some(5001)).unwrap();
+    assert!(items.set_client_id(server_id + 1, Some(1)).is_err());
+
+    assert!(items.remove(FLUID_ID).is_some());
+    assert!(items.remove(FLUID_ID).is_none());
+
+    let mut saved = Vec::new();
+    items.serialize(&mut saved).unwrap();
+    let reloaded = itemtypes::Container::new(&saved[..]).unwrap();
+
+    assert_eq!(items.version, reloaded.version);
+    assert_eq!(items.description, reloaded.description);
+    assert!(!reloaded.items.contains_key(FLUID_ID as usize));
+    assert_eq!(
+        itemtypes::Item {
+            client_id: Some(5001),
+            ..custom
+        },
+        reloaded.items[server_id as usize]
+    );
+    assert_eq!(items.items, reloaded.items);
+}
+
+#[test]
+fn otb_item_types_keep_server_ids_unique() {
+    let data = OtbBuilder::new((3, 57, 0))
+        .item(1, 0, 100, 100)
+        .item(2, 0, 100, 101)
+        .build();
+    assert!(itemtypes::Container::new(&data[4..]).is_err());
+
+    let mut items = item_types();
+    let mut moved = items.remove(STACKABLE_ID).unwrap();
+    moved.server_id = FLUID_ID;
+    items.items.insert(STACKABLE_ID as usize, moved);
+
+    assert!(items.serialize(Vec::new()).is_err());
+}
+
+#[test]
+fn otb_failed_save_keeps_the_old_file() {
+    let path = std::env::temp_dir().join(format!("mapeditor-{}-items.otb", std::process::id()));
+
+    let mut items = item_types();
+    items.save_file(&path).unwrap();
+    let saved = std::fs::read(&path).unwrap();
+
+    // Stored under another server id, which can't be written
+    let mut moved = items.remove(STACKABLE_ID).unwrap();
+    moved.server_id = FLUID_ID;
+    items.items.insert(STACKABLE_ID as usize, moved);
+
+    assert!(items.save_file(&path).is_err());
+    assert!(std::fs::read(&path).unwrap() == saved);
+    assert!(!path.with_extension("otb.tmp").exists());
+
+    std::fs::remove_file(&path).unwrap();
+}
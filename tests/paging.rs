@@ -0,0 +1,240 @@
+//! Checks loading the areas of a map on demand through an area index.
+
+mod common;
+
+use std::io::Write;
+
+use mapeditor::compression::{Compression, Encoder, FileData};
+use mapeditor::opentibia::map::{AreaIndex, AreaPager, Item, Loader, Tile};
+use mapeditor::opentibia::Position;
+
+use common::*;
+
+fn paged_map(data: &[u8]) -> mapeditor::map::Map {
+    let mut loader = Loader::open(data).unwrap();
+    loader.set_item_types(&item_types());
+
+    let index = loader.build_index(data).unwrap();
+    let data = FileData::Decompressed(data.to_vec());
+
+    AreaPager::new(data, loader, index).into_map().unwrap()
+}
+
+#[test]
+fn area_index_round_trips() {
+    let data = sample_map(2).save();
+    let loader = Loader::open(&data[..]).unwrap();
+    let index = loader.build_index(&data).unwrap();
+
+    // Sorted by floor, then by the area's origin
+    let origins: Vec<_> = index.areas.iter().map(|area| area.origin).collect();
+    assert_eq!(5, origins.len());
+    assert_eq!(Position { x: 0, y: 0, z: 7 }, origins[2]);
+
+    // Spawns, towns and waypoints
+    assert_eq!(3, index.others.len());
+
+    let mut saved = Vec::new();
+    index.serialize(&mut saved).unwrap();
+    assert_eq!(index, AreaIndex::deserialize(&saved[..]).unwrap());
+}
+
+#[test]
+fn paged_map_loads_areas_on_demand() {
+    let data = sample_map(2).save();
+    let (_, expected) = load(&data);
+
+    let mut map = paged_map(&data);
+
+    assert_eq!(0, map.tiles().count());
+    assert_eq!(2, map.towns().count());
+    assert_eq!(1, map.spawns().count());
+    assert!(!map.is_fully_loaded());
+
+    // Only the area holding the sector is loaded
+    let temple = Position {
+        x: 0x00FE,
+        y: 0x00FD,
+        z: 7,
+    };
+
+    map.page_in(&temple).unwrap();
+    assert_eq!(expected.get_tile(&temple), map.get_tile(&temple));
+    assert_eq!(1, map.tiles().count());
+
+    let mut saved = Vec::new();
+    let loader = map.pager().unwrap().loader();
+    assert!(loader.save(&mut saved, &map).is_err());
+
+    map.page_in_all().unwrap();
+    assert!(map.is_fully_loaded());
+    assert_eq!(tiles(&expected), tiles(&map));
+
+    let loader = map.pager().unwrap().loader();
+    loader.save(&mut saved, &map).unwrap();
+    assert!(data == saved);
+}
+
+#[test]
+fn paged_map_keeps_tiles_edited_before_they_are_paged_in() {
+    let data = sample_map(2).save();
+    let mut map = paged_map(&data);
+
+    let temple = Position {
+        x: 0x00FE,
+        y: 0x00FD,
+        z: 7,
+    };
+
+    let edited = Tile {
+        items: vec![Item {
+            id: 4526,
+            ..Default::default()
+        }],
+        ..Default::default()
+    };
+
+    *map.get_or_create(&temple).get_tile(&temple) = edited.clone();
+    map.page_in(&temple).unwrap();
+    assert_eq!(Some(&edited), map.get_tile(&temple));
+
+    map.page_in_all().unwrap();
+    assert_eq!(Some(&edited), map.get_tile(&temple));
+}
+
+#[test]
+fn paged_map_size_counts_tiles_not_paged_in_yet() {
+    let data = sample_map(2).save();
+    let (_, expected) = load(&data);
+
+    let mut map = paged_map(&data);
+    map.update_size().unwrap();
+
+    assert!(map.is_fully_loaded());
+    assert_eq!(expected.header(), map.header());
+}
+
+#[test]
+fn paged_map_keeps_its_index_next_to_the_file() {
+    let data = sample_map(2).save();
+    let path = std::env::temp_dir().join(format!("mapeditor-{}-paged.otbm", std::process::id()));
+    let index_path = AreaPager::index_path(&path);
+
+    std::fs::write(&path, &data).unwrap();
+
+    let map = AreaPager::open(&path, Default::default(), &item_types()).unwrap();
+    assert_eq!(0, map.tiles().count());
+
+    let index = std::fs::read(&index_path).unwrap();
+    let index = AreaIndex::deserialize(&index[..]).unwrap();
+    assert_eq!(data.len() as u64, index.file_len);
+
+    // An index that doesn't match the file is rebuilt
+    let stale = AreaIndex {
+        file_len: 1,
+        ..index.clone()
+    };
+
+    let mut saved = Vec::new();
+    stale.serialize(&mut saved).unwrap();
+    std::fs::write(&index_path, saved).unwrap();
+
+    let mut map = AreaPager::open(&path, Default::default(), &item_types()).unwrap();
+    map.page_in_all().unwrap();
+    assert_eq!(tiles(&load(&data).1), tiles(&map));
+
+    let rebuilt = std::fs::read(&index_path).unwrap();
+    assert_eq!(index, AreaIndex::deserialize(&rebuilt[..]).unwrap());
+
+    std::fs::remove_file(&path).unwrap();
+    std::fs::remove_file(&index_path).unwrap();
+}
+
+#[test]
+fn paged_map_can_be_saved_over_its_file() {
+    let data = sample_map(2).save();
+    let path = std::env::temp_dir().join(format!("mapeditor-{}-resave.otbm", std::process::id()));
+
+    std::fs::write(&path, &data).unwrap();
+
+    let mut map = AreaPager::open(&path, Default::default(), &item_types()).unwrap();
+    map.page_in(&Position { x: 0, y: 0, z: 7 }).unwrap();
+    map.page_in_all().unwrap();
+
+    // The file is no longer needed once everything is loaded
+    map.pager()
+        .unwrap()
+        .loader()
+        .save_file(&path, &map)
+        .unwrap();
+
+    map.page_in_all().unwrap();
+    assert!(std::fs::read(&path).unwrap() == data);
+    assert!(!path.with_extension("otbm.tmp").exists());
+
+    std::fs::remove_file(&path).unwrap();
+    std::fs::remove_file(AreaPager::index_path(&path)).unwrap();
+}
+
+#[test]
+fn broken_area_index_is_an_error_or_rebuilt() {
+    let data = sample_map(2).save();
+    let loader = Loader::open(&data[..]).unwrap();
+    let index = loader.build_index(&data).unwrap();
+    assert!(index.fits(data.len() as u64));
+
+    let mut broken = index.clone();
+    broken.areas[0].range.end = data.len() + 10;
+    assert!(!broken.fits(data.len() as u64));
+
+    let mut reversed = index.clone();
+    reversed.others[0] = std::ops::Range { start: 20, end: 10 };
+    assert!(!reversed.fits(data.len() as u64));
+
+    // Loading through a broken index fails instead of panicking
+    let mut loader = Loader::open(&data[..]).unwrap();
+    loader.set_item_types(&item_types());
+    let pager = AreaPager::new(FileData::Decompressed(data.clone()), loader, broken.clone());
+    let mut map = pager.into_map().unwrap();
+    assert!(map.page_in_all().is_err());
+
+    // A saved index that doesn't fit the file is rebuilt
+    let path = std::env::temp_dir().join(format!("mapeditor-{}-broken.otbm", std::process::id()));
+    let index_path = AreaPager::index_path(&path);
+    std::fs::write(&path, &data).unwrap();
+
+    let map = AreaPager::open(&path, Default::default(), &item_types()).unwrap();
+    drop(map);
+
+    let saved = std::fs::read(&index_path).unwrap();
+    let mut saved = AreaIndex::deserialize(&saved[..]).unwrap();
+    saved.areas[0].range.end = data.len() + 10;
+
+    let mut bytes = Vec::new();
+    saved.serialize(&mut bytes).unwrap();
+    std::fs::write(&index_path, bytes).unwrap();
+
+    let mut map = AreaPager::open(&path, Default::default(), &item_types()).unwrap();
+    map.page_in_all().unwrap();
+    assert_eq!(tiles(&load(&data).1), tiles(&map));
+
+    std::fs::remove_file(&path).unwrap();
+    std::fs::remove_file(&index_path).unwrap();
+}
+
+#[test]
+fn compressed_maps_are_not_paged() {
+    let data = sample_map(2).save();
+    let path = std::env::temp_dir().join(format!("mapeditor-{}-paged.otbm.gz", std::process::id()));
+
+    let mut w = Encoder::new(Vec::new(), Compression::Gzip);
+    w.write_all(&data).unwrap();
+    std::fs::write(&path, w.finish().unwrap()).unwrap();
+
+    let map = AreaPager::open(&path, Default::default(), &item_types()).unwrap();
+    assert!(map.is_fully_loaded());
+    assert_eq!(tiles(&load(&data).1), tiles(&map));
+
+    std::fs::remove_file(&path).unwrap();
+    std::fs::remove_file(AreaPager::index_path(&path)).unwrap();
+}
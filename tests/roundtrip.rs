@@ -0,0 +1,525 @@
+//! Checks that saving a map or item types and loading them back doesn't lose
+//! or change anything.
+
+mod common;
+
+use std::collections::BTreeSet;
+use std::io::Read;
+
+use mapeditor::compression::{self, Compression};
+use mapeditor::opentibia::binaryfile::{self, Node};
+use mapeditor::opentibia::itemtypes::{self, ItemGroup};
+use mapeditor::opentibia::map::{
+    ItemAttribute, LoadOptions, Loader, MapError, Monster, NodeKind, Spawn,
+};
+use mapeditor::opentibia::{Position, RawAttribute};
+
+use common::*;
+
+#[test]
+fn otbm_load_returns_the_saved_map() {
+    for version in 0..=Loader::MAX_VERSION {
+        let mut builder = sample_map(version);
+        let data = builder.save();
+        let (_, map) = load(&data);
+
+        assert_maps_eq(&builder.map, &map);
+    }
+}
+
+#[test]
+fn otbm_saved_map_loads_again() {
+    for version in 0..=Loader::MAX_VERSION {
+        let data = sample_map(version).save();
+        let (loader, map) = load(&data);
+
+        let mut saved = Vec::new();
+        loader.save(&mut saved, &map).unwrap();
+
+        let (_, reloaded) = load(&saved);
+
+        assert_maps_eq(&map, &reloaded);
+    }
+}
+
+#[test]
+fn otbm_save_is_byte_exact_after_load() {
+    for version in 0..=Loader::MAX_VERSION {
+        let data = sample_map(version).save();
+        let (loader, map) = load(&data);
+
+        let mut saved = Vec::new();
+        loader.save(&mut saved, &map).unwrap();
+
+        assert!(data == saved, "version {} changed on save", version);
+    }
+}
+
+#[test]
+fn otbm_sample_covers_every_node_kind() {
+    let data = sample_map(2).save();
+    let mut kinds = BTreeSet::new();
+
+    binaryfile::streaming_parser(&data[4..], false, |kind, _| {
+        kinds.insert(kind);
+        Ok(true)
+    })
+    .unwrap();
+
+    let expected: BTreeSet<u8> = [
+        NodeKind::Root,
+        NodeKind::MapData,
+        NodeKind::TileArea,
+        NodeKind::Tile,
+        NodeKind::Item,
+        NodeKind::HouseTile,
+        NodeKind::Spawns,
+        NodeKind::SpawnArea,
+        NodeKind::Monster,
+        NodeKind::Towns,
+        NodeKind::Town,
+        NodeKind::WayPoints,
+        NodeKind::WayPoint,
+    ]
+    .iter()
+    .map(|&kind| kind as u8)
+    .collect();
+
+    assert_eq!(expected, kinds);
+
+    // The attribute values are chosen to need escaping
+    assert!(data.contains(&Node::ESCAPE));
+}
+
+#[test]
+fn otbm_loaders_agree() {
+    let data = sample_map(2).save();
+    let (_, expected) = load(&data);
+
+    let mut loader = Loader::open(&data[..]).unwrap();
+    let mut map = mapeditor::map::Map::new();
+
+    loader
+        .load_slice(&data, |pos, tile| {
+            *map.get_or_create(&pos).get_tile(&pos) = tile
+        })
+        .unwrap();
+
+    loader.move_into(&mut map);
+    assert_maps_eq(&expected, &map);
+
+    for threads in 1..=4 {
+        let mut loader = Loader::open(&data[..]).unwrap();
+        let map = loader.load_parallel(&data, threads).unwrap();
+
+        assert_maps_eq(&expected, &map);
+    }
+}
+
+#[test]
+fn otbm_compressed_files_round_trip() {
+    let mut builder = sample_map(2);
+    let data = builder.save();
+    let dir = std::env::temp_dir();
+
+    for (name, compression) in &[
+        ("roundtrip.otbm", Compression::None),
+        ("roundtrip.otbm.gz", Compression::Gzip),
+        ("roundtrip.otbm.xz", Compression::Xz),
+    ] {
+        let path = dir.join(format!("mapeditor-{}-{}", std::process::id(), name));
+        builder.loader.save_file(&path, &builder.map).unwrap();
+
+        let raw = std::fs::read(&path).unwrap();
+        assert_eq!(*compression, Compression::detect(&raw));
+
+        // Both through a memory-mapped file and a stream
+        let file_data = compression::read_file(&path).unwrap();
+        assert!(data[..] == file_data[..]);
+
+        let mut streamed = Vec::new();
+        compression::decoder(&raw[..])
+            .unwrap()
+            .read_to_end(&mut streamed)
+            .unwrap();
+        assert!(data == streamed);
+
+        // The loader decompresses while reading the stream itself
+        let (_, map) = load(&raw);
+        assert_maps_eq(&builder.map, &map);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
+
+#[test]
+fn otbm_unknown_attributes_are_kept() {
+    const ATTR_DESCRIPTION: u8 = 1;
+    const ATTR_TILE_FLAGS: u8 = 3;
+    const ATTR_ACTION_ID: u8 = 4;
+    const ATTR_ITEM: u8 = 9;
+
+    let data = write_nodes(|w| {
+        w.begin(NodeKind::Root as u8).unwrap();
+        w.write_data(&[2, 0, 0, 0, 0, 1, 0, 1, 3, 0, 0, 0, 57, 0, 0, 0])
+            .unwrap();
+
+        w.begin(NodeKind::MapData as u8).unwrap();
+        w.write_data(&[ATTR_DESCRIPTION, 1, 0, b'a', 0xA0, 0xFF, 0xFE])
+            .unwrap();
+
+        w.begin(NodeKind::TileArea as u8).unwrap();
+        w.write_data(&[0, 0, 0, 0, 7]).unwrap();
+
+        w.begin(NodeKind::Tile as u8).unwrap();
+        w.write_data(&[1, 2, ATTR_TILE_FLAGS, 1, 0, 0, 0, ATTR_ITEM, 100, 0])
+            .unwrap();
+        w.write_data(&[0x7F, 0xFD, ATTR_ACTION_ID, 1, 0]).unwrap();
+
+        w.begin(NodeKind::Item as u8).unwrap();
+        w.write_data(&[101, 0, ATTR_ACTION_ID, 7, 0, 0x90, 1, 2, 3])
+            .unwrap();
+        w.end().unwrap();
+
+        w.end().unwrap();
+        w.end().unwrap();
+
+        w.begin(NodeKind::Towns as u8).unwrap();
+        w.end().unwrap();
+
+        w.end().unwrap();
+        w.end().unwrap();
+    });
+
+    // Unknown attributes are rejected unless loading leniently
+    let mut r = &data[..];
+    let strict = Loader::open(&mut r);
+    assert!(strict.is_err());
+
+    let lenient = LoadOptions {
+        lenient: true,
+        ..Default::default()
+    };
+    let (loader, mut map) = load_with(&data, lenient);
+
+    let warnings: Vec<_> = loader
+        .warnings
+        .iter()
+        .map(|warning| match *warning {
+            MapError::UnknownAttribute {
+                kind,
+                position,
+                attribute,
+                ..
+            } => (kind, position, attribute),
+            _ => panic!("unexpected warning {}", warning),
+        })
+        .collect();
+
+    let tile_pos = Some(Position { x: 1, y: 2, z: 7 });
+    assert_eq!(
+        vec![
+            (NodeKind::MapData, None, 0xA0),
+            (NodeKind::Tile, tile_pos, 0x7F),
+            (NodeKind::Item, tile_pos, 0x90),
+        ],
+        warnings
+    );
+
+    let raw = |kind, data: &[u8]| RawAttribute {
+        kind,
+        data: data.to_vec(),
+    };
+
+    assert_eq!(
+        Some(raw(0xA0, &[0xFF, 0xFE])),
+        map.header().unknown_attribute
+    );
+
+    let pos = Position { x: 1, y: 2, z: 7 };
+    let tile = map.get_tile(&pos).unwrap();
+
+    assert_eq!(
+        Some(&raw(0x7F, &[0xFD, ATTR_ACTION_ID, 1, 0])),
+        tile.unknown_attribute.as_deref()
+    );
+    assert_eq!(
+        vec![
+            ItemAttribute::ActionId(7),
+            ItemAttribute::Unknown(raw(0x90, &[1, 2, 3]))
+        ],
+        tile.items[1].attributes
+    );
+
+    let mut saved = Vec::new();
+    loader.save(&mut saved, &map).unwrap();
+    assert!(data == saved);
+
+    // Attributes added later are still written before the unknown one
+    let item = &mut map.get_tile_mut(&pos).unwrap().items[1];
+    item.attributes.push(ItemAttribute::UniqueId(8));
+
+    let mut saved = Vec::new();
+    loader.save(&mut saved, &map).unwrap();
+
+    let (_, reloaded) = load_with(
+        &saved,
+        LoadOptions {
+            lenient: true,
+            ..Default::default()
+        },
+    );
+    assert_eq!(
+        vec![
+            ItemAttribute::ActionId(7),
+            ItemAttribute::UniqueId(8),
+            ItemAttribute::Unknown(raw(0x90, &[1, 2, 3]))
+        ],
+        reloaded.get_tile(&pos).unwrap().items[1].attributes
+    );
+}
+
+#[test]
+fn otbm_unknown_custom_attribute_types_keep_the_rest_of_the_item() {
+    const ATTR_ACTION_ID: u8 = 4;
+    const ATTR_ATTRIBUTE_MAP: u8 = 128;
+
+    // A double, followed by a value of an unknown type
+    let mut attribute_map = vec![2, 0, 1, 0, b'd', 5];
+    attribute_map.extend_from_slice(&1.5f64.to_le_bytes());
+    attribute_map.extend_from_slice(&[1, 0, b'x', 0x30, 1, 2, 3]);
+
+    let data = write_nodes(|w| {
+        w.begin(NodeKind::Root as u8).unwrap();
+        w.write_data(&[2, 0, 0, 0, 0, 1, 0, 1, 3, 0, 0, 0, 57, 0, 0, 0])
+            .unwrap();
+
+        w.begin(NodeKind::MapData as u8).unwrap();
+
+        w.begin(NodeKind::TileArea as u8).unwrap();
+        w.write_data(&[0, 0, 0, 0, 7]).unwrap();
+
+        w.begin(NodeKind::Tile as u8).unwrap();
+        w.write_data(&[1, 2]).unwrap();
+
+        w.begin(NodeKind::Item as u8).unwrap();
+        w.write_data(&[101, 0, ATTR_ACTION_ID, 7, 0, ATTR_ATTRIBUTE_MAP])
+            .unwrap();
+        w.write_data(&attribute_map).unwrap();
+        w.end().unwrap();
+
+        w.end().unwrap();
+        w.end().unwrap();
+
+        w.begin(NodeKind::Towns as u8).unwrap();
+        w.end().unwrap();
+
+        w.end().unwrap();
+        w.end().unwrap();
+    });
+
+    let mut r = &data[..];
+    let mut strict = Loader::open(&mut r).unwrap();
+    strict.set_item_types(&item_types());
+    assert!(strict.load(&mut r, |_, _| ()).is_err());
+
+    let lenient = LoadOptions {
+        lenient: true,
+        ..Default::default()
+    };
+    let (loader, map) = load_with(&data, lenient);
+
+    let pos = Position { x: 1, y: 2, z: 7 };
+    assert!(matches!(
+        loader.warnings[..],
+        [MapError::UnknownCustomAttributeType {
+            position: Some(position),
+            ..
+        }] if position == pos
+    ));
+
+    assert_eq!(
+        vec![
+            ItemAttribute::ActionId(7),
+            ItemAttribute::Unknown(RawAttribute {
+                kind: ATTR_ATTRIBUTE_MAP,
+                data: attribute_map,
+            })
+        ],
+        map.get_tile(&pos).unwrap().items[0].attributes
+    );
+
+    let mut saved = Vec::new();
+    loader.save(&mut saved, &map).unwrap();
+    assert!(data == saved);
+}
+
+// Ground stored as an Item node instead of inline, and an empty WayPoints
+// node, are both valid but never written
+#[test]
+fn otbm_non_canonical_input_loads_the_same() {
+    const ATTR_DESCRIPTION: u8 = 1;
+    const ATTR_ITEM: u8 = 9;
+
+    let data = write_nodes(|w| {
+        w.begin(NodeKind::Root as u8).unwrap();
+        let mut header = Vec::new();
+        header.extend_from_slice(&2u32.to_le_bytes());
+        header.extend_from_slice(&0x1FFu16.to_le_bytes());
+        header.extend_from_slice(&0x100u16.to_le_bytes());
+        header.extend_from_slice(&3u32.to_le_bytes());
+        header.extend_from_slice(&57u32.to_le_bytes());
+        w.write_data(&header).unwrap();
+
+        w.begin(NodeKind::MapData as u8).unwrap();
+        w.write_data(&[ATTR_DESCRIPTION, 4, 0]).unwrap();
+        w.write_data(b"test").unwrap();
+
+        w.begin(NodeKind::TileArea as u8).unwrap();
+        w.write_data(&[0x00, 0x01, 0x00, 0x01, 7]).unwrap();
+
+        w.begin(NodeKind::Tile as u8).unwrap();
+        w.write_data(&[0xFE, 0xFF]).unwrap();
+        w.begin(NodeKind::Item as u8).unwrap();
+        w.write_data(&100u16.to_le_bytes()).unwrap();
+        w.end().unwrap();
+        w.end().unwrap();
+
+        w.begin(NodeKind::Tile as u8).unwrap();
+        w.write_data(&[0xFD, 0xFF, ATTR_ITEM, 100, 0]).unwrap();
+        w.end().unwrap();
+
+        w.end().unwrap();
+
+        w.begin(NodeKind::Towns as u8).unwrap();
+        w.end().unwrap();
+        w.begin(NodeKind::WayPoints as u8).unwrap();
+        w.end().unwrap();
+
+        w.end().unwrap();
+        w.end().unwrap();
+    });
+
+    let (loader, map) = load(&data);
+
+    let tile = map
+        .get_tile(&Position {
+            x: 0x1FE,
+            y: 0x1FF,
+            z: 7,
+        })
+        .unwrap();
+    assert_eq!(vec![item(100)], tile.items);
+    assert_eq!(2, tiles(&map).len());
+
+    let mut saved = Vec::new();
+    loader.save(&mut saved, &map).unwrap();
+    assert!(data != saved);
+
+    let (loader, reloaded) = load(&saved);
+    assert_maps_eq(&map, &reloaded);
+
+    let mut saved_again = Vec::new();
+    loader.save(&mut saved_again, &reloaded).unwrap();
+    assert!(saved == saved_again);
+}
+
+#[test]
+fn otb_loads_the_built_item_types() {
+    let data = OtbBuilder::new((3, 0xFE, 0xFDFF))
+        .item(1, 0, 0xFEFD, 0xFFFE)
+        .item(5, 1 << 7, STACKABLE_ID, 3031)
+        .item(12, 0, FLUID_ID, 2874)
+        .build();
+
+    let items = itemtypes::Container::new(&data[4..]).unwrap();
+
+    assert_eq!((3, 0xFE, 0xFDFF), items.version);
+    assert_eq!("OTB round-trip test \u{fe}", items.description);
+    assert_eq!(3, items.items.len());
+
+    let ground = &items.items[0xFEFD];
+    assert_eq!(
+        (ItemGroup::Ground, Some(0xFFFE)),
+        (ground.group, ground.client_id)
+    );
+
+    assert!(items.items[STACKABLE_ID as usize].is_stackable());
+    assert!(items.items[FLUID_ID as usize].is_fluid_container());
+}
+
+#[test]
+fn otb_nodes_are_byte_exact_after_load() {
+    let data = OtbBuilder::new((3, 57, 0))
+        .item(1, 0xFFFF_FFFF, 0xFFFF, 0xFDFD)
+        .item(11, 0, SPLASH_ID, 2886)
+        .build();
+
+    let root = Node::deserialize(&mut &data[4..], false).unwrap();
+
+    let mut saved = vec![0; 4];
+    root.serialize(&mut saved).unwrap();
+
+    assert!(data == saved);
+}
+
+#[test]
+fn otbm_spawns_are_loaded_into_the_map() {
+    let center = Position {
+        x: 100,
+        y: 100,
+        z: 7,
+    };
+
+    let mut builder = MapBuilder::new(2);
+    builder.tile(center, vec![item(STACKABLE_ID)]);
+    builder.map.update_size().unwrap();
+
+    builder
+        .map
+        .add_spawn(Spawn {
+            center,
+            radius: 5,
+            monsters: vec![
+                Monster {
+                    name: "Dragon".into(),
+                    offset: (-5, 5),
+                    spawn_time: 90,
+                },
+                Monster {
+                    name: "Dragon Lord".into(),
+                    offset: (3, -2),
+                    spawn_time: 0,
+                },
+            ],
+        })
+        .unwrap();
+
+    let (loader, map) = load(&builder.save());
+    assert!(loader.spawns.is_empty());
+
+    let spawns: Vec<_> = map.spawns().collect();
+    assert_eq!(1, spawns.len());
+    assert_eq!((center, 5), (spawns[0].center, spawns[0].radius));
+
+    let monsters: Vec<_> = spawns[0]
+        .monsters
+        .iter()
+        .map(|monster| {
+            let pos = monster.position(center);
+            (
+                monster.name.as_str(),
+                (pos.x, pos.y, pos.z),
+                monster.spawn_time,
+            )
+        })
+        .collect();
+
+    assert_eq!(
+        vec![
+            ("Dragon", (95, 105, 7), 90),
+            ("Dragon Lord", (103, 98, 7), 0),
+        ],
+        monsters
+    );
+}